@@ -91,6 +91,95 @@ mod tests {
         [id1, id2, id3, id4, id5, id6]
     }
 
+    lazy_static::lazy_static! {
+        // Library::create switches working directory, keep those tests from racing.
+        static ref TEMP_LIBRARY_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    }
+
+    fn create_temp_library(
+        name: &str,
+        features: LibraryFeatures,
+    ) -> (std::sync::MutexGuard<'static, ()>, Library) {
+        let guard = TEMP_LIBRARY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir();
+        fs::remove_dir_all(dir.join(format!("{}.mlib", name)));
+        let lib = Library::create(
+            dir.to_str().unwrap().to_string(),
+            name.to_string(),
+            None,
+            None,
+            features,
+        )
+        .expect("Creating library failed.");
+        (guard, lib)
+    }
+
+    fn write_temp_image(name: &str, width: u32, height: u32) -> String {
+        let path = std::env::temp_dir().join(name);
+        let img = image::RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 128])
+        });
+        img.save(&path).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // Images in distinct sizes so that they never dedup, named "<name>.png".
+    fn add_temp_images(lib: &mut Library, names: &[String]) -> Vec<u64> {
+        let mut ids = vec![];
+        for name in names {
+            let width = 8 + lib.get_summary().media_count as u32;
+            let image = write_temp_image(&format!("{}.png", name), width, 8);
+            ids.push(lib.add_media(image, MediaType::Image, None, None, None, None).unwrap());
+        }
+        ids
+    }
+
+    // Library "<name>" with count images named "<name>_<i>.png", ids in that order.
+    fn library_with_images(
+        name: &str,
+        count: usize,
+    ) -> (std::sync::MutexGuard<'static, ()>, Library, Vec<u64>) {
+        let (guard, mut lib) = create_temp_library(name, LibraryFeatures::new());
+        let names: Vec<String> = (0..count).map(|i| format!("{}_{}", name, i)).collect();
+        let ids = add_temp_images(&mut lib, &names);
+        (guard, lib, ids)
+    }
+
+    // Animated GIF whose frames are in different colors.
+    fn write_temp_gif(name: &str, frames: u32, delay_ms: u32) -> String {
+        let path = std::env::temp_dir().join(name);
+        let mut gif = image::codecs::gif::GifEncoder::new(std::fs::File::create(&path).unwrap());
+        for i in 0..frames {
+            let color = image::Rgba([(i * 255 / frames) as u8, 64, 192, 255]);
+            gif.encode_frame(image::Frame::from_parts(
+                image::RgbaImage::from_pixel(64, 48, color),
+                0,
+                0,
+                image::Delay::from_numer_denom_ms(delay_ms, 1),
+            ))
+            .unwrap();
+        }
+        path.to_str().unwrap().to_string()
+    }
+
+    // JPEG with an EXIF APP1 segment carrying only the orientation tag.
+    fn write_temp_oriented_jpeg(name: &str, width: u32, height: u32, orientation: u8) -> String {
+        let path = std::env::temp_dir().join(name);
+        let img = image::RgbImage::from_pixel(width, height, image::Rgb([200, 100, 50]));
+        let mut jpeg: Vec<u8> = vec![];
+        image::DynamicImage::ImageRgb8(img)
+            .write_to(&mut jpeg, image::ImageFormat::Jpeg)
+            .unwrap();
+        let mut tiff: Vec<u8> = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0".to_vec();
+        tiff.extend_from_slice(&[orientation, 0, 0, 0, 0, 0, 0, 0]);
+        let mut app1: Vec<u8> = vec![0xFF, 0xE1, 0, (tiff.len() + 8) as u8];
+        app1.extend_from_slice(b"Exif\0\0");
+        app1.extend_from_slice(&tiff);
+        jpeg.splice(2..2, app1);
+        fs::write(&path, jpeg).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
     // #[test]
     fn it_works() {
         let mut lib = Library::open("test.mlib".to_string()).expect("?");
@@ -226,4 +315,1140 @@ mod tests {
         println!("Uuid of tag is {}", uuid);
         Ok(())
     }
+
+    #[test]
+    fn test_normalize_url() -> std::result::Result<(), crate::misc::Error> {
+        let url = tools::normalize_url(
+            "HTTPS://Doc.Rust-Lang.org:443/stable/std/?utm_source=feed&b=2&fbclid=x#",
+        )?;
+        assert_eq!(url.as_str(), "https://doc.rust-lang.org/stable/std/?b=2");
+        let url = tools::normalize_url("http://example.com:80/?utm_medium=mail")?;
+        assert_eq!(url.as_str(), "http://example.com/");
+        // pairs are sorted but not encoded again
+        let url = tools::normalize_url("http://example.com/dir?q=a%20b&c&A=1")?;
+        assert_eq!(url.as_str(), "http://example.com/dir?A=1&c&q=a%20b");
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_snapshot() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library(
+            "test_url_snapshot",
+            LibraryFeatures::new().with(LibraryFeature::GenerateThumbnailAtAdding),
+        );
+        let id = lib.add_url(
+            "https://Example.com/page/?utm_source=x".to_string(),
+            None,
+            None,
+            None,
+            None,
+        )?;
+        assert!(matches!(
+            lib.add_url(
+                "https://example.com:443/page/".to_string(),
+                None,
+                None,
+                None,
+                None
+            ),
+            Err(Error::AlreadyExists(_))
+        ));
+        let snapshot = std::env::temp_dir().join("test_url_snapshot.html");
+        fs::write(&snapshot, "<html>archived</html>")?;
+        let screenshot = write_temp_image("test_url_snapshot.png", 64, 48);
+        lib.attach_snapshot(
+            id,
+            snapshot.to_str().unwrap().to_string(),
+            Some(screenshot.clone()),
+            None,
+        )?;
+        lib.wait_workers();
+        let path = lib.get_snapshot_path(id)?.unwrap();
+        assert_eq!(fs::read_to_string(&path)?, "<html>archived</html>");
+        assert!(!Library::wait_thumbnail(lib.get_thumbnail(id))?.is_empty());
+        assert!(format!("{}", lib.get_media(id)?).contains("test_url_snapshot.html (0.02 KB"));
+
+        // the screenshot can still be added as media, and removing it keeps the snapshot
+        let image_id = lib.add_media(screenshot, MediaType::Image, None, None, None, None)?;
+        lib.remove_media(image_id)?;
+        assert!(!Library::wait_thumbnail(lib.regenerate_thumbnail(id))?.is_empty());
+        lib.remove_media(id)?;
+        assert!(!std::path::Path::new(&path).exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_xmp_sidecar() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_xmp_sidecar", LibraryFeatures::new());
+        let image = write_temp_image("test_xmp_sidecar.png", 32, 32);
+        fs::write(
+            std::env::temp_dir().join("test_xmp_sidecar.xmp"),
+            r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+                tiff:Make="FUJIFILM" tiff:Model="X-T4" exif:FNumber="28/10"
+                exif:DateTimeOriginal="2023-05-01T10:20:30+09:00"
+                exif:GPSLatitude="35,40.5N" exif:GPSLongitude="139,46.2E">
+                <dc:subject><rdf:Bag><rdf:li>trip</rdf:li><rdf:li>tokyo</rdf:li></rdf:Bag></dc:subject>
+                </rdf:Description></rdf:RDF></x:xmpmeta>"#,
+        )?;
+        let id = lib.add_media(image, MediaType::Image, None, None, None, None)?;
+        lib.detailize(id)?;
+        let media = lib.get_media(id)?;
+        let metadata = media.get_image_metadata().unwrap();
+        assert_eq!(metadata.f_number, Some(2.8));
+        assert_eq!(metadata.keywords, vec!["trip", "tokyo"]);
+        assert!((metadata.gps.as_ref().unwrap().latitude - 35.675).abs() < 1e-9);
+        assert_eq!(lib.query_media_by_camera("fujifilm x-t4")?, vec![id]);
+        let day = chrono::NaiveDate::from_ymd_opt(2023, 5, 1).unwrap();
+        assert_eq!(
+            lib.query_media_by_capture_time(
+                Some(day.and_hms_opt(0, 0, 0).unwrap()),
+                Some(day.and_hms_opt(10, 20, 30).unwrap())
+            )?,
+            vec![id]
+        );
+        assert!(lib
+            .query_media_by_capture_time(None, Some(day.and_hms_opt(10, 0, 0).unwrap()))?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_oriented_thumbnail() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) =
+            create_temp_library("test_oriented_thumbnail", LibraryFeatures::new());
+        let image = write_temp_oriented_jpeg("test_oriented_thumbnail.jpg", 64, 32, 6);
+        let id = lib.add_media(image, MediaType::Image, None, None, None, None)?;
+        lib.detailize(id)?;
+        let media = lib.get_media(id)?;
+        assert!(format!("{}", media).contains("Image Resolution: 32 x 64"));
+        let thumb = Library::wait_thumbnail(lib.regenerate_thumbnail(id))?;
+        let thumb = image::load_from_memory(&thumb)?.to_rgb8();
+        assert!(thumb.height() > thumb.width());
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated_jpeg_metadata() -> std::result::Result<(), crate::misc::Error> {
+        let image = write_temp_oriented_jpeg("test_truncated_jpeg_metadata.jpg", 64, 32, 6);
+        let mut jpeg = fs::read(&image)?;
+        // cut in the length of the segment after EXIF
+        let exif_end = 4 + u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        jpeg.truncate(exif_end + 3);
+        fs::write(&image, jpeg)?;
+        let metadata = ImageMetadata::from_file(&image)?.unwrap();
+        assert_eq!(metadata.orientation, Some(6));
+        Ok(())
+    }
+
+    #[test]
+    fn test_detailize_at_adding() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library(
+            "test_detailize_at_adding",
+            LibraryFeatures::new().with(LibraryFeature::DetailizeAtAdding),
+        );
+        let image = write_temp_image("test_detailize_at_adding.png", 40, 30);
+        let id = lib.add_media(image, MediaType::Image, None, None, None, None)?;
+        lib.wait_workers();
+        let media = lib.get_media(id)?;
+        assert!(format!("{}", media).contains("Image Resolution: 40 x 30"));
+        assert_eq!(lib.detailize_missing()?, 0);
+
+        // details written before dhash existed are detailized again
+        lib.db.get()?.execute(
+            "UPDATE media_detail SET details = json_remove(details, '$.detail.Image.dhash');",
+            [],
+        )?;
+        assert_eq!(lib.detailize_missing()?, 1);
+        lib.wait_workers();
+        assert_eq!(lib.detailize_missing()?, 0);
+        match lib.get_media(id)?.detail.unwrap().detail {
+            crate::media::TypesDetail::Image(detail) => assert!(detail.dhash.is_some()),
+            _ => panic!("not an image detail"),
+        }
+
+        // text is not supported yet, the failure is kept instead of retried
+        let text = std::env::temp_dir().join("test_detailize_at_adding.txt");
+        std::fs::write(&text, "text")?;
+        let text = text.to_str().unwrap().to_string();
+        let text_id = lib.add_media(text, MediaType::Text, None, None, None, None)?;
+        lib.wait_workers();
+        let failures = lib.get_detail_failures()?;
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, text_id);
+        assert_eq!(lib.detailize_missing()?, 0);
+        lib.clear_detail_failures()?;
+        assert_eq!(lib.detailize_missing()?, 1);
+        lib.wait_workers();
+        assert_eq!(lib.get_detail_failures()?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbnail_sized() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_thumbnail_sized", LibraryFeatures::new());
+        let image = write_temp_image("test_thumbnail_sized.png", 400, 200);
+        let id = lib.add_media(image, MediaType::Image, None, None, None, None)?;
+
+        let icon = ThumbnailSpec::icon().with_format(ThumbnailFormat::Png);
+        let thumb = Library::wait_thumbnail(lib.get_thumbnail_sized(id, &icon))?;
+        let thumb = image::load_from_memory_with_format(&thumb, image::ImageFormat::Png)?.to_rgb8();
+        assert_eq!((thumb.width(), thumb.height()), (96, 48));
+
+        let retina = ThumbnailSpec::icon().scaled(2).with_quality(60);
+        let thumb = Library::wait_thumbnail(lib.get_thumbnail_sized(id, &retina))?;
+        let thumb =
+            image::load_from_memory_with_format(&thumb, image::ImageFormat::Jpeg)?.to_rgb8();
+        assert_eq!((thumb.width(), thumb.height()), (192, 96));
+
+        let webp = ThumbnailSpec::preview().with_format(ThumbnailFormat::WebP);
+        let thumb = Library::wait_thumbnail(lib.get_thumbnail_sized(id, &webp))?;
+        assert_eq!((&thumb[..4], &thumb[8..12]), (&b"RIFF"[..], &b"WEBP"[..]));
+        // cached one is returned as it is
+        assert_eq!(
+            Library::wait_thumbnail(lib.get_thumbnail_sized(id, &webp))?,
+            thumb
+        );
+        assert_eq!(lib.get_thumbnail_specs(None)?.len(), 3);
+
+        // another quality replaces the cached one
+        let _ =
+            Library::wait_thumbnail(lib.get_thumbnail_sized(id, &retina.clone().with_quality(90)))?;
+        assert_eq!(lib.get_thumbnail_specs(None)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_thumbnail_eviction() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) =
+            create_temp_library("test_thumbnail_eviction", LibraryFeatures::new());
+        let image = write_temp_image("test_thumbnail_eviction_1.png", 400, 200);
+        let first = lib.add_media(image, MediaType::Image, None, None, None, None)?;
+        let image = write_temp_image("test_thumbnail_eviction_2.png", 200, 400);
+        let second = lib.add_media(image, MediaType::Image, None, None, None, None)?;
+
+        let spec = ThumbnailSpec::icon().with_format(ThumbnailFormat::Png);
+        let first_size = Library::wait_thumbnail(lib.get_thumbnail_sized(first, &spec))?.len();
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let _ = Library::wait_thumbnail(lib.get_thumbnail_sized(second, &spec))?;
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        // touch the first one so that the second one is the least recently used
+        let _ = Library::wait_thumbnail(lib.get_thumbnail_sized(first, &spec))?;
+
+        lib.set_thumbnail_budget(Some(first_size))?;
+        assert_eq!(lib.get_thumbnail_usage()?, first_size);
+        assert_eq!(lib.get_thumbnail_specs(None)?.len(), 1);
+        assert_eq!(
+            lib.get_thumbnail_specs(Some(&lib.get_media(first)?.hash))?
+                .len(),
+            1
+        );
+
+        lib.set_thumbnail_budget(None)?;
+        assert_eq!(lib.get_thumbnail_budget()?, config::THUMBNAIL_BUDGET);
+        lib.remove_media(first)?;
+        assert_eq!(lib.get_thumbnail_usage()?, 0);
+        assert_eq!(lib.remove_orphan_thumbnails()?, 0);
+        lib.compact_thumbnails()?;
+        Ok(())
+    }
+
+    struct ThreadWaker(std::thread::Thread);
+
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let waker = std::sync::Arc::new(ThreadWaker(std::thread::current())).into();
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                std::task::Poll::Ready(output) => return output,
+                std::task::Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_thumbnail_request() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) =
+            create_temp_library("test_thumbnail_request", LibraryFeatures::new());
+        assert!(matches!(
+            Library::wait_thumbnail(lib.get_thumbnail(42)),
+            Err(Error::NotExists(_))
+        ));
+        let image = write_temp_image("test_thumbnail_request.png", 400, 200);
+        let id = lib.add_media(image, MediaType::Image, None, None, None, None)?;
+
+        let spec = ThumbnailSpec::icon();
+        let requests: Vec<ThumbnailRequest> = (0..4)
+            .map(|_| lib.request_thumbnail(id, &spec, ThumbnailPriority::High))
+            .collect();
+        let cancelled = lib.request_thumbnail(id, &spec, ThumbnailPriority::Low);
+        cancelled.cancel();
+        let thumbs = requests
+            .into_iter()
+            .map(|r| r.wait())
+            .collect::<crate::misc::Result<Vec<_>>>()?;
+        assert!(thumbs.windows(2).all(|w| w[0] == w[1]));
+        assert!(matches!(cancelled.wait(), Err(Error::Cancelled)));
+        assert_eq!(lib.get_thumbnail_specs(None)?.len(), 1);
+
+        let future = lib.request_thumbnail(id, &spec, ThumbnailPriority::Normal);
+        assert_eq!(block_on(future)?, thumbs[0]);
+        let future =
+            lib.request_thumbnail(id, &ThumbnailSpec::preview(), ThumbnailPriority::Normal);
+        assert!(!block_on(future)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_animated_thumbnail() -> std::result::Result<(), crate::misc::Error> {
+        use image::AnimationDecoder;
+        let (_guard, mut lib) =
+            create_temp_library("test_animated_thumbnail", LibraryFeatures::new());
+        let gif = write_temp_gif("test_animated_thumbnail.gif", 6, 50);
+        let id = lib.add_media(gif, MediaType::Image, None, None, None, None)?;
+        lib.detailize(id)?;
+        assert!(format!("{}", lib.get_media(id)?).contains("Animation: 6 frames, 0.30s"));
+
+        // 20 fps is reduced to 10 fps
+        let spec = ThumbnailSpec::icon()
+            .with_format(ThumbnailFormat::Gif)
+            .with_animation(true);
+        let thumb = Library::wait_thumbnail(lib.get_thumbnail_sized(id, &spec))?;
+        let frames = image::codecs::gif::GifDecoder::new(&thumb[..])?
+            .into_frames()
+            .collect_frames()?;
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].delay().numer_denom_ms(), (100, 1));
+
+        let spec = spec.with_format(ThumbnailFormat::WebP);
+        let thumb = Library::wait_thumbnail(lib.get_thumbnail_sized(id, &spec))?;
+        let webp = image_webp::WebPDecoder::new(std::io::Cursor::new(thumb)).unwrap();
+        assert!(webp.is_animated());
+        assert_eq!((webp.num_frames(), webp.loop_duration()), (3, 300));
+        assert_eq!(webp.dimensions(), (96, 72));
+
+        // still one for the other formats
+        let spec = spec.with_format(ThumbnailFormat::Png);
+        let thumb = Library::wait_thumbnail(lib.get_thumbnail_sized(id, &spec))?;
+        assert!(image::load_from_memory_with_format(&thumb, image::ImageFormat::Png).is_ok());
+        assert_eq!(lib.get_thumbnail_specs(None)?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_series_sheet() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_series_sheet", LibraryFeatures::new());
+        let series = lib.create_series("Sheet".to_string(), None)?;
+        assert!(lib.render_series_sheet(&series, 2, 64, None).is_err());
+        assert!(matches!(
+            lib.render_series_sheet(&series, 0, 64, None),
+            Err(Error::TypeMismatch { .. })
+        ));
+        let names: Vec<String> = (0..3).map(|i| format!("test_series_sheet_{}", i)).collect();
+        for id in add_temp_images(&mut lib, &names) {
+            lib.add_to_series(id, &series, None, false)?;
+        }
+
+        let sheet = lib.render_series_sheet(&series, 2, 64, None)?;
+        let image =
+            image::load_from_memory_with_format(&sheet, image::ImageFormat::Jpeg)?.to_rgb8();
+        assert_eq!((image.width(), image.height()), (2 * 68 + 4, 2 * 68 + 4));
+        // cached until members changed
+        assert_eq!(lib.render_series_sheet(&series, 2, 64, None)?, sheet);
+        let image = write_temp_image("test_series_sheet_3.png", 50, 50);
+        let id = lib.add_media(image, MediaType::Image, None, None, None, None)?;
+        lib.add_to_series(id, &series, None, true)?;
+        let sheet = lib.render_series_sheet(&series, 4, 64, None)?;
+        let image =
+            image::load_from_memory_with_format(&sheet, image::ImageFormat::Jpeg)?.to_rgb8();
+        assert_eq!((image.width(), image.height()), (4 * 68 + 4, 64 + 8));
+
+        // captions are drawn under tiles, with a font found in the system
+        let fonts = [
+            "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+            "/System/Library/Fonts/Supplemental/Arial.ttf",
+            "C:\\Windows\\Fonts\\arial.ttf",
+        ];
+        if let Some(font) = fonts.iter().find(|f| std::path::Path::new(f).is_file()) {
+            let captioned = lib.render_series_sheet(&series, 4, 64, Some(font))?;
+            let image =
+                image::load_from_memory_with_format(&captioned, image::ImageFormat::Jpeg)?
+                    .to_rgb8();
+            assert_eq!((image.width(), image.height()), (4 * 68 + 4, 64 + 12 + 8));
+            assert!((68..80).any(|y| (4..68).any(|x| image.get_pixel(x, y)[0] > 128)));
+            let mut media = lib.get_media(id)?;
+            media.caption = Some("renamed".to_string());
+            lib.update_media(&mut media)?;
+            assert_ne!(lib.render_series_sheet(&series, 4, 64, Some(font))?, captioned);
+            // sheet without captions is kept apart
+            assert_eq!(lib.render_series_sheet(&series, 4, 64, None)?, sheet);
+        }
+
+        // sheets count in the thumbnail budget and are evicted with thumbnails
+        assert!(lib.get_thumbnail_usage()? > sheet.len());
+        lib.set_thumbnail_budget(Some(0))?;
+        assert_eq!(lib.get_thumbnail_usage()?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_palette() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_palette", LibraryFeatures::new());
+        let path = std::env::temp_dir().join("test_palette.png");
+        image::RgbImage::from_fn(60, 40, |x, _| match x {
+            0..=44 => image::Rgb([220, 30, 30]),
+            _ => image::Rgb([20, 40, 200]),
+        })
+        .save(&path)?;
+        let red = lib.add_media(
+            path.to_str().unwrap().to_string(),
+            MediaType::Image,
+            None,
+            None,
+            None,
+            None,
+        )?;
+        let gradient = write_temp_image("test_palette_gradient.png", 60, 40);
+        let gradient = lib.add_media(gradient, MediaType::Image, None, None, None, None)?;
+        lib.detailize(red)?;
+        lib.detailize(gradient)?;
+
+        let media = format!("{}", lib.get_media(red)?);
+        assert!(media.contains("Palette: #DC1E1E (75%), #1428C8 (25%)"));
+        assert_eq!(lib.query_media_by_color([210, 40, 40], 20.0)?, vec![red]);
+        assert!(lib.query_media_by_color([20, 200, 20], 30.0)?.is_empty());
+        let blues = lib.query_media_by_color([20, 40, 200], 120.0)?;
+        assert_eq!(blues[0], red);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_hierarchy() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib, ids) = library_with_images("test_tag_hierarchy", 3);
+        let persian = lib.create_tag_path("animal/cat/persian")?;
+        let animal = lib.get_tag_by_caption("animal")?;
+        let cat = lib.get_tag_by_caption("cat")?;
+        let dog = lib.create_tag_path("animal/dog")?;
+        assert_eq!(lib.get_root_tags()?, vec![animal]);
+        assert_eq!(lib.get_tag_children(&cat)?, vec![persian]);
+        assert_eq!(lib.get_tag_descendants(&animal)?.len(), 3);
+        assert_eq!(lib.get_tag_ancestors(&persian)?.len(), 2);
+        assert!(matches!(
+            lib.add_tag_parent(&animal, &persian),
+            Err(Error::Cycle(_))
+        ));
+
+        lib.add_tag(ids[0], &persian)?;
+        lib.add_tag(ids[1], &dog)?;
+        lib.add_tag(ids[2], &cat)?;
+        lib.add_tag(ids[2], &persian)?;
+        assert!(lib.query_media_by_tag(&animal, false)?.is_empty());
+        assert_eq!(lib.query_media_by_tag(&animal, true)?, ids);
+        assert_eq!(lib.query_media_by_tag(&cat, true)?, vec![ids[0], ids[2]]);
+        assert_eq!(lib.get_tag_media_count(&cat, false)?, 1);
+        assert_eq!(lib.get_tag_media_count(&cat, true)?, 2);
+
+        // "dog" goes under "cat"
+        lib.move_tag(&dog, Some(&cat))?;
+        assert_eq!(lib.get_tag_media_count(&cat, true)?, 3);
+        lib.move_tag(&cat, None)?;
+        assert_eq!(lib.get_tag_media_count(&animal, true)?, 0);
+        assert_eq!(lib.get_root_tags()?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_alias() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_tag_alias", LibraryFeatures::new());
+        let cat = lib.create_tag("Cat".to_string(), None)?;
+        let cafe = lib.create_tag("Café".to_string(), None)?;
+        let hiragana = lib.create_tag("ねこ".to_string(), None)?;
+        let dakuten = lib.create_tag("ねご".to_string(), None)?;
+        assert_eq!(lib.get_tag_by_caption("  ｃａｔ ")?, cat);
+        assert_eq!(lib.get_tag_by_caption("CAFE")?, cafe);
+        assert_eq!(lib.get_tag_by_caption("ねこ")?, hiragana);
+        assert_eq!(lib.get_tag_by_caption("ねご")?, dakuten);
+
+        lib.add_tag_alias(&cat, "Kitty")?;
+        assert_eq!(lib.get_tag_by_caption("kitty")?, cat);
+        assert_eq!(lib.get_tag_aliases(&cat)?, vec!["Kitty".to_string()]);
+        assert!(matches!(
+            lib.add_tag_alias(&cafe, "KITTY"),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            lib.add_tag_alias(&cafe, "cat"),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        let caterpillar = lib.create_tag("caterpillar".to_string(), None)?;
+        let id = add_temp_images(&mut lib, &["test_tag_alias".to_string()])[0];
+        lib.add_tag(id, &caterpillar)?;
+        let results = lib.search_tags("cat", 10)?;
+        let uuids: Vec<_> = results.iter().map(|r| r.uuid).collect();
+        assert_eq!(uuids[..2], [caterpillar, cat]);
+        assert_eq!(results[2].uuid, cafe); // one typo away
+        let results = lib.search_tags("kity", 10)?;
+        assert_eq!(results[0].uuid, cat);
+        assert_eq!(results[0].alias.as_deref(), Some("Kitty"));
+        assert_eq!(results[0].distance, 1);
+        assert_eq!(lib.search_tags("caf", 1)?[0].uuid, cafe);
+        // no fuzzy matching for short queries, wildcards are literal
+        let uuids: Vec<_> = lib.search_tags("k", 10)?.iter().map(|r| r.uuid).collect();
+        assert_eq!(uuids, [cat]);
+        assert!(lib.search_tags("%", 10)?.is_empty());
+
+        // only the exact caption gives the existing tag
+        assert_eq!(lib.create_tag("Cat".to_string(), None)?, cat);
+        assert!(matches!(
+            lib.create_tag("kitty".to_string(), None),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            lib.create_tag("CAT".to_string(), None),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        lib.remove_tag_alias("KITTY")?;
+        assert!(lib.get_tag_by_caption("kitty").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_implication() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib, ids) = library_with_images("test_tag_implication", 2);
+        let animal = lib.create_tag("animal".to_string(), None)?;
+        let cat = lib.create_tag("cat".to_string(), None)?;
+        let persian = lib.create_tag("persian".to_string(), None)?;
+        // tagged before the rules
+        lib.add_tag(ids[0], &persian)?;
+
+        lib.add_tag_implication(&persian, &cat)?;
+        lib.add_tag_implication(&cat, &animal)?;
+        assert!(matches!(
+            lib.add_tag_implication(&animal, &persian),
+            Err(Error::Cycle(_))
+        ));
+        assert_eq!(lib.get_tag_implications(&persian)?, vec![cat]);
+        assert_eq!(lib.get_implied_tags(&persian)?.len(), 2);
+
+        assert_eq!(lib.preview_add_tag(ids[1], &persian)?.len(), 3);
+        lib.add_tag(ids[1], &persian)?;
+        assert_eq!(lib.query_media_by_tag(&animal, false)?, vec![ids[1]]);
+        assert!(lib.preview_add_tag(ids[1], &cat)?.is_empty());
+
+        assert_eq!(lib.apply_implications(true)?, 2);
+        assert_eq!(lib.get_tag_media_count(&cat, false)?, 1);
+        assert_eq!(lib.apply_implications(false)?, 2);
+        assert_eq!(lib.get_tag_media_count(&cat, false)?, 2);
+        assert_eq!(lib.query_media_by_tag(&animal, false)?, ids);
+        assert_eq!(lib.apply_implications(false)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_category() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_tag_category", LibraryFeatures::new());
+        let names: Vec<_> = lib
+            .get_tag_categories()?
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(names, ["artist", "character", "copyright", "general", "meta"]);
+
+        let foo = lib.create_tag("Artist:foo".to_string(), None)?;
+        let cat = lib.create_tag("cat".to_string(), None)?;
+        let rezero = lib.create_tag("re:zero".to_string(), None)?;
+        assert_eq!(lib.get_tag(&foo)?.caption, "foo");
+        assert_eq!(lib.get_tag(&foo)?.category.name, "artist");
+        assert_eq!(lib.get_tag(&rezero)?.category.name, "general");
+        assert_eq!(lib.get_tag_by_caption("artist:foo")?, foo);
+        assert_eq!(lib.get_tag_by_caption("foo")?, foo);
+        assert!(lib.get_tag_by_caption("meta:foo").is_err());
+        assert!(matches!(
+            lib.create_tag("meta:foo".to_string(), None),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        lib.create_tag_category("species", "#00ffff", 5)?;
+        assert!(lib.create_tag_category("species", "#00FFFF", 5).is_err());
+        assert!(lib.update_tag_category("species", "cyan", 5).is_err());
+        lib.update_tag_category("species", "#00cccc", -1)?;
+        lib.set_tag_category(&cat, "species")?;
+        assert_eq!(lib.get_tags_by_category("species")?, vec![cat]);
+
+        let id = add_temp_images(&mut lib, &["test_tag_category".to_string()])[0];
+        lib.add_tag(id, &rezero)?;
+        lib.add_tag(id, &foo)?;
+        lib.add_tag(id, &cat)?;
+        let tags = lib.get_media_tags(id)?;
+        let captions: Vec<_> = tags.iter().map(|t| t.caption.as_str()).collect();
+        assert_eq!(captions, ["cat", "foo", "re:zero"]);
+        assert_eq!(tags[0].category.color, "#00CCCC");
+
+        lib.delete_tag_category("species")?;
+        assert_eq!(lib.get_tag(&cat)?.category.name, "general");
+        assert!(lib.delete_tag_category("general").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_bulk() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib, ids) = library_with_images("test_tag_bulk", 4);
+        let cat = lib.create_tag("cat".to_string(), None)?;
+        let dog = lib.create_tag("dog".to_string(), None)?;
+        let animal = lib.create_tag("animal".to_string(), None)?;
+        lib.add_tag_implication(&cat, &animal)?;
+        lib.add_tag(ids[0], &cat)?;
+
+        let changes = lib.add_tags_bulk(&[ids[0], ids[1], ids[2], 9999], &[cat])?;
+        assert_eq!(changes.added, 4);
+        assert_eq!(changes.media, 2);
+        assert_eq!(changes.missing, vec![9999]);
+        assert_eq!(lib.get_tag(&cat)?.media_count, 3);
+        assert_eq!(lib.get_tag(&animal)?.media_count, 3);
+
+        let changes = lib.remove_tags_bulk(&ids, &[cat])?;
+        assert_eq!((changes.removed, changes.media), (3, 3));
+        assert_eq!(lib.get_tag(&cat)?.media_count, 0);
+        assert_eq!(lib.get_tag(&animal)?.media_count, 3);
+
+        let changes = lib.replace_tags(&ids[1..], &[dog])?;
+        assert_eq!((changes.added, changes.removed), (3, 2));
+        assert_eq!(lib.query_media_by_tag(&animal, false)?, vec![ids[0]]);
+        assert_eq!(lib.get_tag(&dog)?.media_count, 3);
+
+        let changes = lib.add_tags_to_query(&MediaQuery::new().without_tag(dog), &[cat])?;
+        assert_eq!((changes.added, changes.media), (1, 1));
+        let query = MediaQuery::new().with_caption("test_tag_bulk_1");
+        assert_eq!(lib.add_tags_to_query(&query, &[cat])?.added, 2);
+        assert_eq!(lib.get_tag(&animal)?.media_count, 2);
+        lib.remove_tag(ids[0], &cat)?;
+        assert_eq!(lib.get_tag(&cat)?.media_count, 1);
+        // the caption is bound as a value, never as sql
+        let query = MediaQuery::new().with_caption("' OR 1 = 1 --");
+        assert_eq!(lib.remove_tags_from_query(&query, &[cat])?.media, 0);
+        let changes = lib.remove_tags_from_query(&MediaQuery::new().with_tag(dog), &[cat])?;
+        assert_eq!((changes.removed, changes.media), (1, 1));
+        assert_eq!(lib.get_tag(&cat)?.media_count, 0);
+        assert!(lib.add_tags_bulk(&ids, &[crate::misc::Uuid::new_v4()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_edit() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib, ids) = library_with_images("test_tag_edit", 3);
+        for id in &ids {
+            lib.detailize(*id)?;
+        }
+        let cat = lib.create_tag("cat".to_string(), None)?;
+        let kitty = lib.create_tag("kitty".to_string(), None)?;
+        let neko = lib.create_tag("neko".to_string(), None)?;
+        let animal = lib.create_tag("animal".to_string(), None)?;
+        lib.add_tag_implication(&kitty, &animal)?;
+        lib.add_tags_bulk(&ids[..2], &[cat])?;
+        lib.add_tags_bulk(&ids[1..], &[kitty, neko])?;
+        let tagged_in_detail = |lib: &Library, tag: &crate::misc::Uuid| {
+            lib.query_media(&format!(
+                "id IN (SELECT id FROM media_detail WHERE tags LIKE '%{}%')",
+                tag
+            ))
+        };
+        assert_eq!(tagged_in_detail(&lib, &neko)?, ids[1..]);
+
+        assert!(matches!(
+            lib.rename_tag(&cat, "Kitty"),
+            Err(Error::AlreadyExists(_))
+        ));
+        lib.rename_tag(&cat, "character:Cat")?;
+        assert_eq!(lib.get_tag(&cat)?.caption, "Cat");
+        assert_eq!(lib.get_tag(&cat)?.category.name, "character");
+
+        let changes = lib.merge_tags(&cat, &[kitty, neko, cat])?;
+        assert_eq!((changes.added, changes.removed, changes.media), (1, 4, 2));
+        assert_eq!(lib.get_tag(&cat)?.media_count, 3);
+        assert!(lib.get_tag(&kitty).is_err());
+        assert_eq!(lib.get_tag_by_caption("kitty")?, cat);
+        assert_eq!(lib.get_implied_tags(&cat)?, vec![animal]);
+        assert!(tagged_in_detail(&lib, &neko)?.is_empty());
+        assert_eq!(tagged_in_detail(&lib, &cat)?, ids);
+
+        // merging into an ancestor or implication would make a cycle
+        let pet = lib.create_tag("pet".to_string(), None)?;
+        let dog = lib.create_tag("dog".to_string(), None)?;
+        lib.add_tag_parent(&pet, &animal)?;
+        lib.add_tag_parent(&dog, &pet)?;
+        lib.add_tag_implication(&dog, &pet)?;
+        assert!(matches!(
+            lib.merge_tags(&dog, &[animal]),
+            Err(Error::Cycle(_))
+        ));
+        assert!(lib.get_tag(&animal).is_ok());
+        assert_eq!(lib.get_tag_ancestors(&dog)?.len(), 2);
+
+        let tag_count = lib.get_summary().tag_count;
+        assert!(matches!(
+            lib.split_tag(&cat, &[ids[0], 9999], "persian".to_string()),
+            Err(Error::NotIn { .. })
+        ));
+        assert!(lib.get_tag_by_caption("persian").is_err());
+        assert_eq!(lib.get_summary().tag_count, tag_count);
+        let persian = lib.split_tag(&cat, &ids[..2], "persian".to_string())?;
+        assert_eq!(lib.get_tag(&persian)?.category.name, "character");
+        assert_eq!(lib.query_media_by_tag(&persian, false)?, ids[..2]);
+        assert_eq!(lib.get_tag(&cat)?.media_count, 1);
+        assert_eq!(tagged_in_detail(&lib, &cat)?, ids[2..]);
+
+        lib.delete_tag(persian)?;
+        assert!(tagged_in_detail(&lib, &persian)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_statistics() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib, ids) = library_with_images("test_tag_statistics", 4);
+        let cat = lib.create_tag("cat".to_string(), None)?;
+        let kitten = lib.create_tag("kitten".to_string(), None)?;
+        let dog = lib.create_tag("dog".to_string(), None)?;
+        let unused = lib.create_tag("unused".to_string(), None)?;
+        lib.add_tags_bulk(&ids[..3], &[cat])?;
+        lib.add_tags_bulk(&ids[..2], &[kitten])?;
+        lib.add_tags_bulk(&ids[2..], &[dog])?;
+
+        let usage = lib.get_tag_usage(None)?;
+        assert_eq!(usage[0], (cat, 3));
+        assert_eq!(usage.last(), Some(&(unused, 0)));
+        assert_eq!(lib.get_tag_usage(Some(2))?.len(), 2);
+        assert_eq!(lib.get_unused_tags()?, vec![unused]);
+
+        let pairs = lib.get_tag_cooccurrence(2, None)?;
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(pairs[0].count, 2);
+        assert!(pairs[0].tags == (cat, kitten) || pairs[0].tags == (kitten, cat));
+        assert_eq!(lib.get_tag_cooccurrence(1, None)?.len(), 2);
+
+        let related = lib.get_related_tags(&cat, 10)?;
+        assert_eq!(related.len(), 2);
+        assert_eq!(related[0].uuid, kitten);
+        assert!((related[0].score - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!((related[1].uuid, related[1].count), (dog, 1));
+        assert!(lib.get_related_tags(&unused, 10)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest_tags() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_suggest_tags", LibraryFeatures::new());
+        let cat = lib.create_tag("cat".to_string(), None)?;
+        let outdoor = lib.create_tag("outdoor".to_string(), None)?;
+        let dog = lib.create_tag("dog".to_string(), None)?;
+        // the gradient one in folder a looks like the target, the reversed ones do not
+        let mut add_image = |folder: &str, name: &str, size: (u32, u32), reversed: bool| {
+            let dir = std::env::temp_dir().join("test_suggest_tags").join(folder);
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(name);
+            image::RgbImage::from_fn(size.0, size.1, |x, _| {
+                let v = (x * 255 / size.0) as u8;
+                image::Rgb([if reversed { 255 - v } else { v }; 3])
+            })
+            .save(&path)
+            .unwrap();
+            let path = path.to_str().unwrap().to_string();
+            let id = lib.add_media(path, MediaType::Image, None, None, None, None)?;
+            lib.detailize(id)?;
+            Ok::<u64, crate::misc::Error>(id)
+        };
+        let similar = add_image("a", "1.png", (16, 16), false)?;
+        let other = add_image("a", "2.png", (16, 16), true)?;
+        let target = add_image("b", "1.png", (20, 12), false)?;
+        let same_folder = add_image("b", "2.png", (12, 20), true)?;
+        lib.add_tags_bulk(&[similar], &[cat, outdoor])?;
+        lib.add_tags_bulk(&[other], &[outdoor, dog])?;
+        lib.add_tag(same_folder, &dog)?;
+
+        let suggestions = lib.suggest_tags(target)?;
+        assert_eq!(suggestions.len(), 3);
+        assert!((suggestions[0].confidence - 1.0 / 1.5).abs() < 1e-9);
+        assert_eq!(suggestions[2].uuid, dog);
+        assert!((suggestions[2].confidence - 0.5 / 1.5).abs() < 1e-9);
+        // nothing is applied
+        assert!(lib.get_media_tags(target)?.is_empty());
+
+        lib.add_tag(target, &cat)?;
+        let suggestions: Vec<_> = lib
+            .suggest_tags(target)?
+            .into_iter()
+            .map(|s| s.uuid)
+            .collect();
+        assert_eq!(suggestions, vec![outdoor, dog]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_series_metadata() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_series_metadata", LibraryFeatures::new());
+        let uuid = lib.create_series("chapters".to_string(), None)?;
+        let names: Vec<String> = ["page10", "page2", "Page1"]
+            .iter()
+            .map(|name| format!("test_series_metadata_{}", name))
+            .collect();
+        let ids = add_temp_images(&mut lib, &names);
+        for id in &ids {
+            lib.add_to_series(*id, &uuid, None, false)?;
+        }
+        let series = lib.get_series(&uuid)?;
+        assert_eq!(series.members, ids);
+        assert_eq!(series.order, SeriesOrder::SeriesNo);
+        assert_eq!(series.cover, Some(ids[0]));
+        assert!(series.time_modify >= series.time_create);
+
+        lib.set_series_order(&uuid, SeriesOrder::Filename)?;
+        assert_eq!(lib.get_series_members(&uuid)?, vec![ids[2], ids[1], ids[0]]);
+        lib.set_series_order(&uuid, SeriesOrder::TimeAdd)?;
+        assert_eq!(lib.get_series_members(&uuid)?, ids);
+
+        lib.set_series_cover(&uuid, Some(ids[1]))?;
+        assert_eq!(lib.get_series(&uuid)?.cover, Some(ids[1]));
+        lib.remove_from_series(ids[1], &uuid)?;
+        assert_eq!(lib.get_series(&uuid)?.cover, Some(ids[0]));
+        assert!(matches!(
+            lib.set_series_cover(&uuid, Some(ids[1])),
+            Err(Error::NotIn { .. })
+        ));
+
+        lib.set_series_attribute(&uuid, "author", serde_json::json!("someone"))?;
+        lib.set_series_attribute(&uuid, "volume", serde_json::json!(3))?;
+        lib.set_series_attribute(&uuid, "volume", serde_json::Value::Null)?;
+        lib.set_series_caption(&uuid, "volume 1".to_string())?;
+        let series = lib.get_series(&uuid)?;
+        assert_eq!(series.caption, "volume 1");
+        assert_eq!(series.attributes.len(), 1);
+        assert_eq!(series.attributes["author"], "someone");
+        assert!(lib.get_series(&crate::misc::Uuid::new_v4()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_series_numbering() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib, ids) = library_with_images("test_series_numbering", 5);
+        let uuid = lib.create_series("pages".to_string(), None)?;
+        let other = lib.create_series("other".to_string(), None)?;
+        let empty = lib.create_series("empty".to_string(), None)?;
+        for id in &ids[..4] {
+            lib.add_to_series(*id, &uuid, None, false)?;
+        }
+        lib.add_to_series(ids[0], &other, None, false)?;
+        assert!(lib
+            .add_to_series(ids[0], &crate::misc::Uuid::new_v4(), None, false)
+            .is_err());
+        lib.trim_series_no(&empty)?;
+
+        // only numbers in the series are touched
+        lib.update_series_no(ids[0], &uuid, 3, true)?;
+        assert_eq!(lib.get_series_members(&uuid)?, [ids[1], ids[0], ids[2], ids[3]]);
+        assert_eq!(lib.get_series_members(&other)?, [ids[0]]);
+        assert!(matches!(
+            lib.update_series_no(ids[4], &uuid, 1, true),
+            Err(Error::NotIn { .. })
+        ));
+
+        lib.move_in_series(ids[3], &uuid, 1)?;
+        assert_eq!(lib.get_series_members(&uuid)?, [ids[3], ids[1], ids[0], ids[2]]);
+        lib.swap_in_series(&uuid, ids[3], ids[2])?;
+        assert_eq!(lib.get_series_members(&uuid)?, [ids[2], ids[1], ids[0], ids[3]]);
+        lib.reorder_series(&uuid, &ids[..4])?;
+        assert_eq!(lib.get_series_members(&uuid)?, ids[..4]);
+        assert!(lib.reorder_series(&uuid, &ids[..3]).is_err());
+
+        lib.insert_into_series(ids[4], &uuid, 2)?;
+        assert_eq!(lib.get_series_members(&uuid)?, [ids[0], ids[4], ids[1], ids[2], ids[3]]);
+        assert_eq!(lib.get_series(&uuid)?.media_count, 5);
+        // members are not added twice
+        assert!(matches!(
+            lib.insert_into_series(ids[4], &uuid, 1),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            lib.add_to_series(ids[0], &uuid, None, false),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert_eq!(lib.get_series_members(&uuid)?, [ids[0], ids[4], ids[1], ids[2], ids[3]]);
+        assert_eq!(lib.get_series(&uuid)?.media_count, 5);
+        lib.remove_from_series(ids[1], &uuid)?;
+        lib.add_to_series(ids[1], &uuid, None, true)?;
+        lib.compact_series_no(&uuid)?;
+        assert_eq!(lib.get_next_no_in_series(&uuid)?, Some(5));
+        assert_eq!(lib.get_series_members(&uuid)?, [ids[0], ids[4], ids[2], ids[3], ids[1]]);
+        lib.move_in_series(ids[1], &uuid, 100)?;
+        assert_eq!(lib.get_next_no_in_series(&uuid)?, Some(6));
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_series() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib, ids) = library_with_images("test_nested_series", 5);
+        let collection = lib.create_series("collection".to_string(), None)?;
+        let volume1 = lib.create_series("volume 1".to_string(), None)?;
+        let volume2 = lib.create_series("volume 2".to_string(), None)?;
+        let chapter = lib.create_series("chapter 1".to_string(), None)?;
+        lib.add_to_series(ids[0], &collection, None, false)?;
+        lib.add_to_series(ids[1], &chapter, None, false)?;
+        lib.add_to_series(ids[2], &chapter, None, false)?;
+        lib.add_to_series(ids[3], &volume2, None, false)?;
+        lib.add_to_series(ids[4], &volume1, None, false)?;
+
+        lib.add_child_series(&collection, &volume2, None)?;
+        lib.add_child_series(&collection, &volume1, None)?;
+        lib.add_child_series(&volume1, &chapter, None)?;
+        assert!(matches!(
+            lib.add_child_series(&chapter, &collection, None),
+            Err(Error::Cycle(_))
+        ));
+        assert!(matches!(
+            lib.add_child_series(&volume2, &chapter, None),
+            Err(Error::Occupied(_))
+        ));
+        assert_eq!(lib.get_series_path(&chapter)?, [collection, volume1, chapter]);
+        assert_eq!(lib.get_parent_series(&collection)?, None);
+
+        lib.reorder_child_series(&collection, &[volume1, volume2])?;
+        assert_eq!(lib.get_child_series(&collection)?, [volume1, volume2]);
+        let flattened = lib.get_series_flattened(&collection)?;
+        assert_eq!(flattened, [ids[0], ids[4], ids[1], ids[2], ids[3]]);
+
+        assert_eq!(lib.get_series_neighbours(&collection, ids[0])?, (None, Some(ids[4])));
+        assert_eq!(lib.get_series_neighbours(&collection, ids[2])?, (Some(ids[1]), Some(ids[3])));
+        assert_eq!(lib.get_series_neighbours(&chapter, ids[2])?, (Some(ids[1]), None));
+        assert!(lib.get_series_neighbours(&volume2, ids[0]).is_err());
+
+        lib.delete_series(&volume1)?;
+        assert_eq!(lib.get_parent_series(&chapter)?, None);
+        assert_eq!(lib.get_series_flattened(&collection)?, [ids[0], ids[3]]);
+
+        let modified = lib.get_series(&collection)?.time_modify;
+        assert!(matches!(
+            lib.remove_child_series(&collection, &chapter),
+            Err(Error::NotIn { .. })
+        ));
+        assert!(matches!(
+            lib.remove_child_series(&volume1, &chapter),
+            Err(Error::NotExists(_))
+        ));
+        assert_eq!(lib.get_series(&collection)?.time_modify, modified);
+        lib.remove_child_series(&collection, &volume2)?;
+        assert_eq!(lib.get_child_series(&collection)?, []);
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_folder() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_import_folder", LibraryFeatures::new());
+        let folder = std::env::temp_dir().join("test_import_folder").join("Trip 2023");
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(folder.join("sub"))?;
+        for (i, name) in ["p10.png", "p2.png", "P1.png", ".hidden.png", "sub/p3.png"]
+            .iter()
+            .enumerate()
+        {
+            let img = image::RgbImage::from_pixel(8 + i as u32, 8, image::Rgb([i as u8, 0, 0]));
+            img.save(folder.join(name)).unwrap();
+        }
+        fs::write(folder.join("notes.xyz"), "not a media")?;
+        let existed = lib.add_media(
+            folder.join("p2.png").to_str().unwrap().to_string(),
+            MediaType::Image,
+            None,
+            None,
+            None,
+            None,
+        )?;
+
+        let result = lib.import_folder(folder.to_str().unwrap(), true)?;
+        assert_eq!(result.added.len(), 2);
+        assert_eq!(result.existed, vec![existed]);
+        assert_eq!(result.skipped, vec!["notes.xyz".to_string()]);
+        assert!(result.failed.is_empty());
+        let series = lib.get_series(&result.series.unwrap())?;
+        assert_eq!(series.caption, "Trip 2023");
+        assert_eq!(series.members, [result.added[0], existed, result.added[1]]);
+        let names: Vec<_> = series
+            .members
+            .iter()
+            .map(|id| lib.get_media(*id).unwrap().filename)
+            .collect();
+        assert_eq!(names, ["P1.png", "p2.png", "p10.png"]);
+
+        // imported again without series
+        let result = lib.import_folder(folder.to_str().unwrap(), false)?;
+        assert!(result.added.is_empty());
+        assert_eq!(result.existed.len(), 3);
+        assert_eq!(result.series, None);
+        assert!(lib.import_folder(folder.join("p2.png").to_str().unwrap(), true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_smart_series() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib, ids) = library_with_images("test_smart_series", 4);
+        let cat = lib.create_tag("cat".to_string(), None)?;
+        let persian = lib.create_tag_path("cat/persian")?;
+        let blurry = lib.create_tag("blurry".to_string(), None)?;
+        let start = chrono::Local::now() - chrono::Duration::seconds(1);
+        lib.add_tag(ids[0], &cat)?;
+        lib.add_tag(ids[1], &persian)?;
+        lib.add_tags_bulk(&ids[2..], &[cat, blurry])?;
+
+        let query = MediaQuery::new()
+            .with_tag(cat)
+            .without_tag(blurry)
+            .with_kind(MediaType::Image)
+            .added_between(Some(start), None);
+        assert_eq!(lib.query_media_by(&query)?, ids[..2]);
+        assert!(lib
+            .query_media_by(&MediaQuery::new().added_between(None, Some(start)))?
+            .is_empty());
+        assert_eq!(
+            lib.query_media_by(&MediaQuery::new().with_caption("SERIES_3"))?,
+            [ids[3]]
+        );
+        assert_eq!(lib.query_media_by(&MediaQuery::new())?, ids);
+
+        let smart = lib.create_smart_series("cats".to_string(), &query)?;
+        assert_eq!(lib.get_series(&smart)?.members, ids[..2]);
+        lib.remove_tag(ids[2], &blurry)?;
+        let series = lib.get_series(&smart)?;
+        assert_eq!(series.members, ids[..3]);
+        assert_eq!(series.media_count, 3);
+        assert!(series.query.is_some());
+        assert!(matches!(
+            lib.add_to_series(ids[3], &smart, None, false),
+            Err(Error::TypeMismatch { .. })
+        ));
+
+        let series_count = lib.get_summary().series_count;
+        let snapshot = lib.snapshot_series(&smart, "cats today".to_string())?;
+        assert_eq!(lib.get_summary().series_count, series_count + 1);
+        lib.remove_tag(ids[0], &cat)?;
+        assert_eq!(lib.get_series_members(&smart)?, ids[1..3]);
+        let series = lib.get_series(&snapshot)?;
+        assert_eq!(series.members, ids[..3]);
+        assert_eq!(series.media_count, 3);
+        assert!(series.query.is_none());
+        assert!(matches!(
+            lib.set_series_query(&snapshot, Some(&query)),
+            Err(Error::Occupied(_))
+        ));
+        // members of smart series are not numbered
+        assert!(lib.move_in_series(ids[1], &smart, 2).is_err());
+        assert!(lib.swap_in_series(&smart, ids[1], ids[2]).is_err());
+        assert!(lib.reorder_series(&smart, &ids[1..3]).is_err());
+        assert!(lib.compact_series_no(&smart).is_err());
+        assert!(!lib.render_series_sheet(&smart, 2, 16, None)?.is_empty());
+
+        // queries follow merged tags and drop deleted ones
+        let kitty = lib.create_tag("kitty".to_string(), None)?;
+        lib.merge_tags(&kitty, &[cat])?;
+        lib.delete_tag(blurry)?;
+        let stored = lib.get_series_query(&smart)?.unwrap();
+        assert_eq!((stored.tags, stored.exclude_tags), (vec![kitty], vec![]));
+        assert_eq!(lib.get_series_members(&smart)?, ids[1..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_saved_search() -> std::result::Result<(), crate::misc::Error> {
+        let (_guard, mut lib) = create_temp_library("test_saved_search", LibraryFeatures::new());
+        let cat = lib.create_tag("cat".to_string(), None)?;
+        let names: Vec<String> = ["b10", "b2", "a1"]
+            .iter()
+            .map(|name| format!("test_saved_search_{}", name))
+            .collect();
+        let ids = add_temp_images(&mut lib, &names);
+        for (id, caption) in ids[1..].iter().zip(["bravo", "alpha"]) {
+            let mut media = lib.get_media(*id)?;
+            media.caption = Some(caption.to_string());
+            lib.update_media(&mut media)?;
+        }
+        lib.add_tags_bulk(&ids, &[cat])?;
+
+        let query = MediaQuery::new().with_tag(cat);
+        let uuid = lib.create_saved_search("cats".to_string(), &query)?;
+        assert!(matches!(
+            lib.create_saved_search("cats".to_string(), &MediaQuery::new()),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert_eq!(lib.find_saved_search("cats")?, Some(uuid));
+        assert_eq!(lib.run_saved_search(&uuid)?, ids);
+        lib.set_saved_search_sort(&uuid, MediaSort::Filename, false)?;
+        assert_eq!(lib.run_saved_search(&uuid)?, [ids[2], ids[1], ids[0]]);
+        lib.set_saved_search_sort(&uuid, MediaSort::Caption, true)?;
+        assert_eq!(lib.run_saved_search(&uuid)?, [ids[1], ids[2], ids[0]]);
+        lib.set_saved_search_option(&uuid, "view", serde_json::json!("grid"))?;
+        lib.rename_saved_search(&uuid, "all cats".to_string())?;
+        let other = lib.create_saved_search("captioned".to_string(), &query.with_caption("ALPHA"))?;
+        assert!(matches!(
+            lib.rename_saved_search(&other, "all cats".to_string()),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        let exported = lib.export_saved_searches()?;
+        let searches = lib.get_saved_searches()?;
+        assert_eq!(searches.len(), 2);
+        assert_eq!(searches[0].name, "all cats");
+        assert_eq!(searches[0].sort, MediaSort::Caption);
+        assert!(searches[0].descending);
+        assert_eq!(searches[0].options["view"], "grid");
+        lib.delete_saved_search(&uuid)?;
+        assert!(matches!(lib.get_saved_search(&uuid), Err(Error::NotExists(_))));
+        lib.rename_saved_search(&other, "renamed".to_string())?;
+
+        assert_eq!(lib.import_saved_searches(&exported)?, 2);
+        let search = lib.get_saved_search(&uuid)?;
+        assert_eq!(search.name, "all cats");
+        assert_eq!(search.options["view"], "grid");
+        assert_eq!(lib.get_saved_search(&other)?.name, "captioned");
+        assert_eq!(lib.run_saved_search(&other)?, [ids[2]]);
+
+        // names must be unique within the import too
+        let mut searches = lib.get_saved_searches()?;
+        for search in searches.iter_mut() {
+            search.uuid = crate::misc::Uuid::new_v4();
+            search.name = "twice".to_string();
+        }
+        assert!(matches!(
+            lib.import_saved_searches(&serde_json::to_string(&searches)?),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert_eq!(lib.find_saved_search("twice")?, None);
+
+        // only the names after the import count, two searches may swap theirs
+        let mut searches = lib.get_saved_searches()?;
+        let first = searches[0].name.clone();
+        searches[0].name = searches[1].name.clone();
+        searches[1].name = first;
+        assert_eq!(lib.import_saved_searches(&serde_json::to_string(&searches)?)?, 2);
+        assert_eq!(lib.get_saved_search(&uuid)?.name, "captioned");
+        assert_eq!(lib.get_saved_search(&other)?.name, "all cats");
+        // a name taken by a search outside the import still conflicts
+        let mut searches = lib.get_saved_searches()?;
+        searches.truncate(1);
+        searches[0].name = "captioned".to_string();
+        assert!(matches!(
+            lib.import_saved_searches(&serde_json::to_string(&searches)?),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        // queries follow merged tags
+        let feline = lib.create_tag("feline".to_string(), None)?;
+        lib.merge_tags(&feline, &[cat])?;
+        assert_eq!(lib.get_saved_search(&other)?.query.tags, [feline]);
+        assert_eq!(lib.run_saved_search(&other)?, [ids[2]]);
+        Ok(())
+    }
 }
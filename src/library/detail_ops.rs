@@ -0,0 +1,233 @@
+use std::path;
+
+use chrono::NaiveDateTime;
+use rusqlite::params;
+
+use super::super::media::{Media, MediaDetail};
+use super::super::misc::Result;
+use super::{Library, SQLite};
+
+pub(crate) const DETAIL_FAILURE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS media_detail_failure(
+        media_id INTEGER PRIMARY KEY NOT NULL UNIQUE,
+        reason TEXT NOT NULL,
+        FOREIGN KEY(media_id) REFERENCES media(id)
+    );
+    ";
+
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+impl Library {
+    pub fn detailize(&mut self, id: u64) -> Result<()> {
+        let mut media = self.get_media(id)?.detailize(None);
+        if let Some(xmp) = self.find_xmp_sidecar(id)? {
+            media.apply_xmp_sidecar(&xmp);
+        }
+        self.update_media(&mut media)
+    }
+
+    // Detail extraction may take time on large images, so it runs on thread pool.
+    // Failures are recorded instead of retried, see get_detail_failures.
+    pub(crate) fn detailize_in_background(&self, id: u64) -> Result<()> {
+        let media = self.get_media(id)?;
+        let xmp = self.find_xmp_sidecar(id)?;
+        let db = self.db.clone();
+        // values given at adding are kept when detailized again
+        let other = media.detail.as_ref().map(|detail| detail.other.clone());
+        self.thread_pool.execute(move || {
+            let result = media.try_detailize(other).and_then(|detail| {
+                let mut media = Media { detail, ..media };
+                if let Some(xmp) = xmp {
+                    media.apply_xmp_sidecar(&xmp);
+                }
+                match &media.detail {
+                    Some(detail) => write_media_detail(&db, media.id, detail),
+                    None => Ok(()),
+                }
+            });
+            if let Err(e) = result {
+                // nowhere to report if even this fails, it is retried by next detailize_missing
+                let _ = write_detail_failure(&db, id, &e.to_string());
+            }
+        });
+        Ok(())
+    }
+
+    // Queue detail extraction for media that have neither detail nor a recorded failure,
+    // and images detailized before dhash existed. Return the count of queued.
+    // Use wait_workers to wait for them.
+    pub fn detailize_missing(&self) -> Result<usize> {
+        let ids: Vec<u64> = self
+            .db
+            .get()?
+            .prepare(
+                "SELECT id FROM media WHERE (id NOT IN (SELECT id FROM media_detail)
+                        OR id IN (SELECT id FROM media_detail
+                            WHERE json_type(details, '$.detail.Image') IS NOT NULL
+                                AND json_type(details, '$.detail.Image.dhash') IS NULL))
+                    AND id NOT IN (SELECT media_id FROM media_detail_failure);",
+            )?
+            .query_map(params![], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        for id in ids.iter() {
+            self.detailize_in_background(*id)?;
+        }
+        Ok(ids.len())
+    }
+
+    // Media whose detail could not be extracted in background, with the reason.
+    pub fn get_detail_failures(&self) -> Result<Vec<(u64, String)>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare("SELECT media_id, reason FROM media_detail_failure ORDER BY media_id;")?
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Let detailize_missing try the failed media again, e.g. after the decoder is improved.
+    pub fn clear_detail_failures(&mut self) -> Result<()> {
+        self.db
+            .get()?
+            .execute("DELETE FROM media_detail_failure;", params![])?;
+        Ok(())
+    }
+
+    // Sidecar is looked up beside every location the media was added from,
+    // both "IMG_0001.jpg.xmp" and "IMG_0001.xmp" are accepted.
+    fn find_xmp_sidecar(&self, id: u64) -> Result<Option<String>> {
+        let locations: Vec<String> = self
+            .db
+            .get()?
+            .prepare("SELECT path FROM media_location_ref WHERE media_id = ?;")?
+            .query_map(params![id], |row| row.get(0))?
+            .map(|x| x.unwrap())
+            .collect();
+        for location in locations {
+            let location = path::PathBuf::from(location);
+            let candidates = [
+                path::PathBuf::from(format!("{}.xmp", location.to_str().unwrap())),
+                location.with_extension("xmp"),
+                location.with_extension("XMP"),
+            ];
+            if let Some(sidecar) = candidates.iter().find(|p| p.is_file()) {
+                return Ok(Some(std::fs::read_to_string(sidecar)?));
+            }
+        }
+        Ok(None)
+    }
+
+    // Both bounds are inclusive, capture time is the local time recorded by camera.
+    pub fn query_media_by_capture_time(
+        &self,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<Vec<u64>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "SELECT id FROM (
+                    SELECT id, json_extract(details, '$.detail.Image.metadata.capture_time') AS t
+                    FROM media_detail
+                 ) WHERE t IS NOT NULL AND (?1 IS NULL OR t >= ?1) AND (?2 IS NULL OR t <= ?2)
+                 ORDER BY t;",
+            )?
+            .query_map(
+                params![
+                    from.map(|v| v.format(TIME_FORMAT).to_string()),
+                    to.map(|v| v.format(TIME_FORMAT).to_string() + ".999999999"),
+                ],
+                |row| row.get(0),
+            )?
+            .map(|x| x.unwrap())
+            .collect())
+    }
+
+    // Camera matches make, model or "make model", case insensitive.
+    pub fn query_media_by_camera(&self, camera: &str) -> Result<Vec<u64>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "SELECT id FROM (
+                    SELECT id,
+                        lower(json_extract(details, '$.detail.Image.metadata.make')) AS make,
+                        lower(json_extract(details, '$.detail.Image.metadata.model')) AS model
+                    FROM media_detail
+                 ) WHERE make = lower(?1) OR model = lower(?1) OR make || ' ' || model = lower(?1);",
+            )?
+            .query_map(params![camera.trim()], |row| row.get(0))?
+            .map(|x| x.unwrap())
+            .collect())
+    }
+
+    // Media having a dominant color within the distance in RGB space, the nearest goes first.
+    pub fn query_media_by_color(&self, rgb: [u8; 3], threshold: f64) -> Result<Vec<u64>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "SELECT id FROM (
+                    SELECT media_detail.id AS id, MIN(
+                        (json_extract(color.value, '$.rgb[0]') - ?1) * (json_extract(color.value, '$.rgb[0]') - ?1) +
+                        (json_extract(color.value, '$.rgb[1]') - ?2) * (json_extract(color.value, '$.rgb[1]') - ?2) +
+                        (json_extract(color.value, '$.rgb[2]') - ?3) * (json_extract(color.value, '$.rgb[2]') - ?3)
+                    ) AS distance
+                    FROM media_detail, json_each(details, '$.detail.Image.palette') AS color
+                    GROUP BY media_detail.id
+                 ) WHERE distance <= ?4 ORDER BY distance, id;",
+            )?
+            .query_map(
+                params![rgb[0], rgb[1], rgb[2], threshold * threshold],
+                |row| row.get(0),
+            )?
+            .map(|x| x.unwrap())
+            .collect())
+    }
+}
+
+// Column tags of media_detail mirrors media_tag_ref, in uuids split by ','.
+const DETAIL_TAGS: &str =
+    "(SELECT group_concat(tag_uuid, ',') FROM media_tag_ref WHERE media_id = media_detail.id)";
+
+pub(crate) fn write_media_detail(db: &SQLite, id: u64, detail: &MediaDetail) -> Result<()> {
+    let db = db.get()?;
+    db.execute(
+        "INSERT INTO media_detail (id, details) VALUES (?1, ?2)
+            ON CONFLICT(id) DO UPDATE SET details = excluded.details;",
+        params![id, serde_json::to_string(detail)?],
+    )?;
+    db.execute(
+        "DELETE FROM media_detail_failure WHERE media_id = ?;",
+        params![id],
+    )?;
+    sync_detail_tags(&db, &[id])
+}
+
+fn write_detail_failure(db: &SQLite, id: u64, reason: &str) -> Result<()> {
+    db.get()?.execute(
+        "INSERT OR REPLACE INTO media_detail_failure (media_id, reason) VALUES (?, ?);",
+        params![id, reason],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn sync_detail_tags(db: &rusqlite::Connection, ids: &[u64]) -> Result<()> {
+    let mut stmt = db.prepare(&format!(
+        "UPDATE media_detail SET tags = {} WHERE id = ?;",
+        DETAIL_TAGS
+    ))?;
+    for id in ids {
+        stmt.execute(params![id])?;
+    }
+    Ok(())
+}
+
+pub(crate) fn sync_all_detail_tags(db: &rusqlite::Connection) -> Result<()> {
+    db.execute(
+        &format!("UPDATE media_detail SET tags = {};", DETAIL_TAGS),
+        params![],
+    )?;
+    Ok(())
+}
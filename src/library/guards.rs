@@ -2,6 +2,7 @@
 
 use rusqlite::params;
 
+use super::super::media::ThumbnailSpec;
 use super::super::misc::{Error, Result, Uuid};
 use super::Library;
 use crate::get_db_or_false;
@@ -53,15 +54,118 @@ impl Library {
         }
     }
 
-    pub(crate) fn is_thumbnailed(&self, id: u64) -> bool {
+    pub(crate) fn is_series_existed(&self, uuid: &Uuid) -> bool {
+        let db = get_db_or_false!(self.db);
+        db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM series WHERE uuid = ?);",
+            params![uuid],
+            |row| row.get(0),
+        )
+        .unwrap_or(false)
+    }
+
+    pub(crate) fn series_exist_guard(&self, uuid: &Uuid) -> Result<()> {
+        if !self.is_series_existed(uuid) {
+            Err(Error::NotExists(format!(
+                "Series with uuid {} not exists.",
+                uuid
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn is_saved_search_existed(&self, uuid: &Uuid) -> bool {
+        let db = get_db_or_false!(self.db);
+        db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM saved_search WHERE uuid = ?);",
+            params![uuid],
+            |row| row.get(0),
+        )
+        .unwrap_or(false)
+    }
+
+    pub(crate) fn saved_search_exist_guard(&self, uuid: &Uuid) -> Result<()> {
+        if !self.is_saved_search_existed(uuid) {
+            Err(Error::NotExists(format!(
+                "Saved search with uuid {} not exists.",
+                uuid
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    // Members of smart series come from their query only.
+    pub(crate) fn static_series_guard(&self, uuid: &Uuid) -> Result<()> {
+        if self.get_series_query(uuid)?.is_some() {
+            return Err(Error::TypeMismatch {
+                val: uuid.to_string(),
+                expect: "static series".to_string(),
+                found: "smart series".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // Takes the connection so that it can be checked in the transaction changing members.
+    pub(crate) fn is_series_member_existed(
+        db: &rusqlite::Connection,
+        id: u64,
+        uuid: &Uuid,
+    ) -> bool {
+        db.query_row(
+            "SELECT EXISTS(SELECT 1 FROM media_series_ref WHERE series_uuid = ? AND media_id = ?);",
+            params![uuid, id],
+            |row| row.get(0),
+        )
+        .unwrap_or(false)
+    }
+
+    pub(crate) fn series_member_guard(&self, id: u64, uuid: &Uuid) -> Result<()> {
+        self.series_exist_guard(uuid)?;
+        let db = self.db.get()?;
+        if !Self::is_series_member_existed(&db, id, uuid) {
+            return Err(Error::NotIn {
+                a: format!("Media {}", id),
+                b: format!("series {}", uuid),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn series_member_not_exist_guard(
+        db: &rusqlite::Connection,
+        id: u64,
+        uuid: &Uuid,
+    ) -> Result<()> {
+        if Self::is_series_member_existed(db, id, uuid) {
+            return Err(Error::AlreadyExists(format!(
+                "Media {} is already in series {}.",
+                id, uuid
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_thumbnailed(&self, id: u64, spec: &ThumbnailSpec) -> bool {
         let hash = match self.get_media_hash(id) {
             Some(hash) => hash,
             None => return false,
         };
         let thumbnail_db = get_db_or_false!(self.thumbnail_db);
         match thumbnail_db.query_row(
-            "SELECT EXISTS(SELECT 1 FROM thumbnail WHERE hash = ?);",
-            params![hash],
+            "SELECT EXISTS(SELECT 1 FROM thumbnail
+                WHERE hash = ? AND width = ? AND height = ? AND format = ? AND animated = ?
+                    AND quality = ?);",
+            params![
+                hash,
+                spec.width,
+                spec.height,
+                spec.format,
+                spec.animated,
+                spec.quality
+            ],
             |row| Ok(row.get(0)?),
         ) {
             Ok(v) => v,
@@ -0,0 +1,70 @@
+use std::{fs, path::Path};
+
+use super::super::media::MediaType;
+use super::super::misc::{tools, Error, Result};
+use super::series_ops::{insert_series, insert_series_member};
+use super::{FolderImport, Library};
+use crate::err_type_mismatch_expect_dir_found_file;
+
+impl Library {
+    // Add media files directly in the folder, in natural order of filenames. Hidden and unknown files are skipped.
+    // With series, a series named after the folder is created and media are numbered in that order.
+    // Media already in the library are not added again, but still go to the series.
+    pub fn import_folder(&mut self, path: &str, series: bool) -> Result<FolderImport> {
+        let folder = Path::new(path).canonicalize()?;
+        if !folder.is_dir() {
+            return Err(err_type_mismatch_expect_dir_found_file!(path.to_string()));
+        }
+        let mut files = vec![];
+        for entry in fs::read_dir(&folder)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || !entry.file_type()?.is_file() {
+                continue;
+            }
+            files.push((name, entry.path()));
+        }
+        files.sort_by(|a, b| tools::natural_cmp(&a.0, &b.0));
+
+        let mut result = FolderImport::default();
+        let mut imported = vec![];
+        for (name, file) in files {
+            let kind = MediaType::from_path(&file);
+            if kind.is_none() {
+                result.skipped.push(name);
+                continue;
+            }
+            let file = file.to_string_lossy().to_string();
+            match self.add_media_file(file, kind, None, None, None, None) {
+                Ok((id, true)) => {
+                    result.added.push(id);
+                    imported.push(id);
+                }
+                // same file twice in the folder goes to the series once
+                Ok((id, false)) if !imported.contains(&id) => {
+                    result.existed.push(id);
+                    imported.push(id);
+                }
+                Ok(_) => result.skipped.push(name),
+                Err(e) => result.failed.push((name, e.to_string())),
+            }
+        }
+
+        if series && !imported.is_empty() {
+            let caption = folder
+                .file_name()
+                .map_or(path.to_string(), |name| name.to_string_lossy().to_string());
+            // the series is created with all its media or not at all
+            let mut db = self.db.get()?;
+            let tx = db.transaction()?;
+            let uuid = insert_series(&tx, caption, None)?;
+            for (no, id) in imported.iter().enumerate() {
+                insert_series_member(&tx, *id, &uuid, Some(no as u64 + 1), false)?;
+            }
+            tx.commit()?;
+            self.summary.series_count += 1;
+            result.series = Some(uuid);
+        }
+        Ok(result)
+    }
+}
@@ -5,7 +5,17 @@ use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::params;
 
 use super::super::misc::{config, tools, Error, HashAlgo, Lock, LockType, Result, Uuid};
-use super::{Library, LibraryFeatures, LibraryMetadata, LibrarySummary};
+use super::detail_ops::DETAIL_FAILURE_SCHEMA;
+use super::saved_search_ops::SAVED_SEARCH_SCHEMA;
+use super::series_meta_ops::migrate_series;
+use super::series_tree_ops::SERIES_CHILD_SCHEMA;
+use super::sheet_ops::SERIES_SHEET_SCHEMA;
+use super::tag_alias_ops::TAG_ALIAS_SCHEMA;
+use super::tag_category_ops::migrate_tag_category;
+use super::tag_implication_ops::TAG_IMPLICATION_SCHEMA;
+use super::tag_tree_ops::TAG_PARENT_SCHEMA;
+use super::thumbnail::{migrate_thumbnail_db, THUMBNAIL_SCHEMA};
+use super::{Library, LibraryFeatures, LibraryMetadata, LibrarySummary, SQLite};
 
 use semver;
 
@@ -65,6 +75,8 @@ impl Library {
         let db = r2d2::Pool::new(db)?;
         let shared_db = r2d2::Pool::new(shared_db)?;
         let thumbnail_db = r2d2::Pool::new(thumbnail_db)?;
+        migrate_db(&db)?;
+        migrate_thumbnail_db(&thumbnail_db)?;
 
         let path = std::env::current_dir()?.to_str().unwrap().to_string();
         std::env::set_current_dir(current_workdir)?;
@@ -99,6 +111,7 @@ impl Library {
             lock,
             features,
             thread_pool: threadpool::ThreadPool::new(num_cpus::get()),
+            thumbnail_queue: Default::default(),
         })
     }
 
@@ -196,14 +209,22 @@ impl Library {
                    uuid CHAR(36) PRIMARY KEY NOT NULL UNIQUE,
                    caption TEXT NOT NULL,
                    media_count INTEGER,
-                   comment TEXT
+                   comment TEXT,
+                   cover_id INTEGER,
+                   order_mode TEXT NOT NULL DEFAULT 'SERIES_NO',
+                   time_create TIMESTAMP,
+                   time_modify TIMESTAMP,
+                   attributes TEXT, /* json object */
+                   query TEXT /* json of MediaQuery, for smart series only */
                 );
 
                 CREATE TABLE tag(
                    uuid CHAR(36) PRIMARY KEY NOT NULL UNIQUE,
                    caption TEXT UNIQUE NOT NULL,
                    media_count INTEGER,
-                   comment TEXT
+                   comment TEXT,
+                   normalized TEXT, /* caption for matching, see tools::normalize_caption */
+                   category TEXT NOT NULL DEFAULT 'general'
                 );
 
                 CREATE TABLE library(
@@ -251,6 +272,7 @@ impl Library {
                     (?, ?);",
             params![&library_uuid, env::current_dir()?.to_str()],
         )?;
+        migrate_db(&db)?;
         let shared_db = SqliteConnectionManager::file(config::SHARED_DATABASE_FN);
         let thumbnail_db = SqliteConnectionManager::file(config::THUMBNAIL_DATABASE_FN);
         let shared_db = r2d2::Pool::new(shared_db)?;
@@ -258,17 +280,13 @@ impl Library {
         thumbnail_db.get()?.execute_batch(
             "
                 CREATE TABLE metadata(
-                    library_uuid CHAR(36) PRIMARY KEY NOT NULL UNIQUE
-                );
-
-                CREATE TABLE thumbnail(
-                    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
-                    hash CHAR(32) NOT NULL UNIQUE,
-                    image BLOB,
-                    size INTEGER NOT NULL
+                    library_uuid CHAR(36) PRIMARY KEY NOT NULL UNIQUE,
+                    budget INTEGER
                 );
                 ",
         )?;
+        thumbnail_db.get()?.execute_batch(THUMBNAIL_SCHEMA)?;
+        thumbnail_db.get()?.execute_batch(SERIES_SHEET_SCHEMA)?;
         thumbnail_db.get()?.execute(
             "INSERT INTO metadata (library_uuid) VALUES (?);",
             params![&library_uuid],
@@ -303,6 +321,7 @@ impl Library {
             lock,
             features,
             thread_pool: threadpool::ThreadPool::new(num_cpus::get()),
+            thumbnail_queue: Default::default(),
         })
     }
 
@@ -375,6 +394,34 @@ impl Drop for Library {
     }
 }
 
+// Tables added after the first release, which are created if not exists for old libraries.
+fn migrate_db(pool: &SQLite) -> Result<()> {
+    let db = pool.get()?;
+    db.execute_batch(TAG_PARENT_SCHEMA)?;
+    db.execute_batch(TAG_ALIAS_SCHEMA)?;
+    db.execute_batch(TAG_IMPLICATION_SCHEMA)?;
+    db.execute_batch(SERIES_CHILD_SCHEMA)?;
+    db.execute_batch(SAVED_SEARCH_SCHEMA)?;
+    db.execute_batch(DETAIL_FAILURE_SCHEMA)?;
+    if !tools::has_column(&db, "tag", "normalized")? {
+        db.execute("ALTER TABLE tag ADD COLUMN normalized TEXT;", params![])?;
+        let tags: Vec<(Uuid, String)> = db
+            .prepare("SELECT uuid, caption FROM tag;")?
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (uuid, caption) in tags {
+            db.execute(
+                "UPDATE tag SET normalized = ? WHERE uuid = ?;",
+                params![tools::normalize_caption(&caption), uuid],
+            )?;
+        }
+    }
+    drop(db);
+    migrate_tag_category(pool)?;
+    migrate_series(pool)?;
+    Ok(())
+}
+
 impl fmt::Display for Library {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Library name: {}\nMaster name: {}\nUUID: {}\nPath: {}\nschema: {}\nLibrary Summary:\n{}",
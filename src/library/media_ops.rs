@@ -2,8 +2,9 @@ use std::{fs, path, path::Path, str};
 
 use rusqlite::params;
 
-use super::super::media::{Media, MediaType};
-use super::super::misc::{Error, Result, Uuid};
+use super::super::media::{Media, MediaDetail, MediaType, TypesDetail, URLSnapshot};
+use super::super::misc::{config, tools, Error, Result, Uuid};
+use super::detail_ops::write_media_detail;
 use super::{Library, LibraryFeature};
 use crate::{err_type_mismatch_expect_dir_found_file, get_db_or_none};
 
@@ -20,6 +21,22 @@ impl Library {
         if let MediaType::URL = kind {
             return self.add_url(path, sub_kind, kind_addition, caption, comment);
         }
+        match self.add_media_file(path, kind, sub_kind, kind_addition, caption, comment)? {
+            (id, true) => Ok(id),
+            (id, false) => Err(Error::AlreadyExists(id.to_string())),
+        }
+    }
+
+    // Same as add_media for files, but the id of existing one is returned with false.
+    pub(crate) fn add_media_file(
+        &mut self,
+        path: String,
+        kind: MediaType,
+        sub_kind: Option<String>,
+        kind_addition: Option<String>,
+        caption: Option<String>,
+        comment: Option<String>,
+    ) -> Result<(u64, bool)> {
         let media_path = path::PathBuf::from(path);
         if !media_path.is_file() {
             return Err(err_type_mismatch_expect_dir_found_file!(media_path
@@ -50,7 +67,7 @@ impl Library {
                         media_path.file_stem().unwrap().to_str()
                     ],
                 ); // ignore fails
-                Err(Error::AlreadyExists(id.to_string()))
+                Ok((id, false))
             } else {
                 // shouldn't reach
                 Err(Error::AlreadyExists(file_hash))
@@ -85,7 +102,10 @@ impl Library {
             // generate thumbnail image at adding
             let _ = self.make_thumbnail(id);
         }
-        Ok(id)
+        if self.features.contains(LibraryFeature::DetailizeAtAdding) {
+            self.detailize_in_background(id)?;
+        }
+        Ok((id, true))
     }
 
     pub fn add_url(
@@ -96,7 +116,11 @@ impl Library {
         caption: Option<String>,
         comment: Option<String>,
     ) -> Result<u64> {
+        let url = tools::normalize_url(&url)?.to_string();
         let hash = self.hash_algo.do_hash_str(&url)?;
+        if let Some(id) = self.get_media_id(&hash) {
+            return Err(Error::AlreadyExists(id.to_string()));
+        }
         let db = self.db.get()?;
         db.execute(
             "INSERT INTO media (hash, filename, filesize, caption, type, sub_type, type_addition, comment)
@@ -105,10 +129,119 @@ impl Library {
         )?;
         let id = db.last_insert_rowid() as u64;
         self.summary.media_count += 1;
+        if self.features.contains(LibraryFeature::DetailizeAtAdding) {
+            self.detailize_in_background(id)?;
+        }
         Ok(id)
     }
 
+    // Attach an offline copy (html, mhtml, pdf, ...) to url media, replacing the former one.
+    // time_snapshot falls back to the modified time of snapshot file.
+    pub fn attach_snapshot(
+        &mut self,
+        id: u64,
+        snapshot: String,
+        screenshot: Option<String>,
+        time_snapshot: Option<chrono::DateTime<chrono::Local>>,
+    ) -> Result<()> {
+        let media = self.get_media(id)?;
+        if !matches!(media.kind, MediaType::URL) {
+            return Err(Error::TypeMismatch {
+                val: id.to_string(),
+                expect: MediaType::URL.to_string(),
+                found: media.kind.to_string(),
+            });
+        }
+        let mut media = match media.detail {
+            Some(_) => media,
+            None => media.detailize(None),
+        };
+        let snapshot_path = path::PathBuf::from(snapshot);
+        if !snapshot_path.is_file() {
+            return Err(Error::NotExists(snapshot_path.to_str().unwrap().to_string()));
+        }
+        let time_snapshot = match time_snapshot {
+            Some(t) => t,
+            None => snapshot_path.metadata()?.modified()?.into(),
+        };
+        let (hash, filesize) = self.store_snapshot_file(&snapshot_path)?;
+        let (screenshot, screenshot_size) = match screenshot {
+            Some(p) => {
+                let (hash, size) = self.store_snapshot_file(Path::new(&p))?;
+                (Some(hash), size)
+            }
+            None => (None, 0),
+        };
+        let old_snapshot = media.get_url_snapshot().cloned();
+        match &mut media.detail {
+            Some(MediaDetail {
+                detail: TypesDetail::URL(detail),
+                ..
+            }) => {
+                detail.snapshot = Some(URLSnapshot {
+                    hash,
+                    filename: snapshot_path.file_name().unwrap().to_str().unwrap().to_string(),
+                    filesize,
+                    time_snapshot,
+                    screenshot,
+                    screenshot_size,
+                })
+            }
+            _ => {
+                return Err(Error::MediaDecode(format!(
+                    "Cannot get url detail of media {}.",
+                    id
+                )))
+            }
+        };
+        self.summary.media_size -= media.filesize;
+        media.filesize = filesize + screenshot_size;
+        self.summary.media_size += media.filesize;
+        self.update_media(&mut media)?;
+        if let Some(old_snapshot) = old_snapshot {
+            self.remove_snapshot_files(&old_snapshot)?;
+        }
+        // thumbnail of url media comes from the screenshot
+        self.remove_thumbnail(&media.hash)?;
+        if self
+            .features
+            .contains(LibraryFeature::GenerateThumbnailAtAdding)
+            && screenshot_size != 0
+        {
+            let _ = self.make_thumbnail(id);
+        }
+        Ok(())
+    }
+
+    pub fn get_snapshot_path(&self, id: u64) -> Result<Option<String>> {
+        let media = self.get_media(id)?;
+        Ok(media.get_url_snapshot().map(|snapshot| {
+            self.get_snapshot_path_by_hash(&snapshot.hash)
+                .to_str()
+                .unwrap()
+                .to_string()
+        }))
+    }
+
     pub fn remove_media(&mut self, id: u64) -> Result<()> {
+        let media = self.get_media(id)?;
+        if let MediaType::URL = media.kind {
+            // url media have no file but snapshot
+            let db = self.db.get()?;
+            db.execute("DELETE FROM media_detail WHERE id = ?;", params![id])?;
+            db.execute(
+                "DELETE FROM media_detail_failure WHERE media_id = ?;",
+                params![id],
+            )?;
+            db.execute("DELETE FROM media WHERE id = ?;", params![id])?;
+            if let Some(snapshot) = media.get_url_snapshot() {
+                self.remove_snapshot_files(snapshot)?;
+            }
+            self.remove_thumbnail(&media.hash)?;
+            self.summary.media_size -= media.filesize;
+            self.summary.media_count -= 1;
+            return Ok(());
+        }
         let db = self.db.get()?;
         let (file_hash, file_size): (String, usize) = db.query_row(
             "SELECT hash, filesize FROM media WHERE id = ?;",
@@ -120,14 +253,18 @@ impl Library {
         if !media_file.is_file() {
             panic!("Media file is not exists or not a regular file.");
         }
+        db.execute("DELETE FROM media_detail WHERE id = ?;", params![id])?;
+        db.execute(
+            "DELETE FROM media_detail_failure WHERE media_id = ?;",
+            params![id],
+        )?;
         db.execute("DELETE FROM media WHERE id = ?;", params![id])?;
         fs::remove_file(&media_file)?;
         println!("Removed {:?}", media_file);
+        self.remove_thumbnail(&file_hash)?;
         self.summary.media_size -= file_size;
         self.summary.media_count -= 1;
         // TODO: REMOVE series and tag notation
-        // TODO: REMOVE thumbnail if necessary
-        // TODO: REMOVE details if necessary
         Ok(())
     }
 
@@ -169,23 +306,7 @@ impl Library {
             // drop new media from database
         }
         if let Some(detail) = &media.detail {
-            let db = self.db.get()?;
-            let is_detail_exists: bool = db.query_row(
-                "SELECT EXISTS(SELECT 1 FROM media_detail WHERE id = ?);",
-                params![media.id],
-                |row| Ok(row.get(0)?),
-            )?;
-            if is_detail_exists {
-                db.execute(
-                    "UPDATE media_detail SET details = ? WHERE id = ?;",
-                    params![serde_json::to_string(detail)?, media.id],
-                )?;
-            } else {
-                db.execute(
-                    "INSERT INTO media_detail (id, details) VALUES (?, ?);",
-                    params![media.id, serde_json::to_string(detail)?],
-                )?;
-            }
+            write_media_detail(&self.db, media.id, detail)?;
         }
         db.execute(
             "UPDATE media
@@ -260,12 +381,6 @@ impl Library {
         Ok(media)
     }
 
-    pub fn detailize(&self, id: u64) -> Result<()> {
-        let media = self.get_media(id)?;
-        media.detailize(None);
-        Ok(())
-    }
-
     pub fn get_media_by_filename(&self, filename: String) -> Result<Vec<u64>> {
         let db = self.db.get()?;
         let filename_stem = Path::new(&filename).file_stem().unwrap().to_str().unwrap();
@@ -346,7 +461,7 @@ impl Library {
 
 impl Library {
     // private method
-    fn get_media_path_by_hash(&self, hash: &str) -> path::PathBuf {
+    pub(crate) fn get_media_path_by_hash(&self, hash: &str) -> path::PathBuf {
         // this method do not promise the existence.
         path::PathBuf::new()
             .join(self.path.as_str())
@@ -354,4 +469,52 @@ impl Library {
             .join(&hash[..2])
             .join(format!("{}", &hash[2..],))
     }
+
+    // Snapshot files are kept apart from media files, so the same file can be both.
+    pub(crate) fn get_snapshot_path_by_hash(&self, hash: &str) -> path::PathBuf {
+        path::PathBuf::new()
+            .join(self.path.as_str())
+            .join(&self.media_folder)
+            .join(config::SNAPSHOTS_FOLDER)
+            .join(&hash[..2])
+            .join(&hash[2..])
+    }
+
+    // Copy file into snapshot folder by its hash. Return hash and size of the stored file.
+    fn store_snapshot_file(&self, file_path: &Path) -> Result<(String, usize)> {
+        if !file_path.is_file() {
+            return Err(Error::NotExists(file_path.to_str().unwrap().to_string()));
+        }
+        let hash = self
+            .hash_algo
+            .do_hash(file_path.to_str().unwrap().to_string())?;
+        let new_path = self.get_snapshot_path_by_hash(&hash);
+        if !new_path.exists() {
+            fs::create_dir_all(new_path.parent().unwrap())?;
+            fs::copy(file_path, &new_path)?;
+        }
+        let size = new_path.metadata()?.len() as usize;
+        Ok((hash, size))
+    }
+
+    fn is_snapshot_referenced(&self, hash: &str) -> Result<bool> {
+        Ok(self.db.get()?.query_row(
+            "SELECT EXISTS(SELECT 1 FROM media_detail
+                WHERE json_extract(details, '$.detail.URL.snapshot.hash') = ?1
+                    OR json_extract(details, '$.detail.URL.snapshot.screenshot') = ?1);",
+            params![hash],
+            |row| row.get(0),
+        )?)
+    }
+
+    // Remove files of snapshot which no longer referenced by any media.
+    fn remove_snapshot_files(&self, snapshot: &URLSnapshot) -> Result<()> {
+        for hash in std::iter::once(&snapshot.hash).chain(snapshot.screenshot.iter()) {
+            let file = self.get_snapshot_path_by_hash(hash);
+            if file.is_file() && !self.is_snapshot_referenced(hash)? {
+                fs::remove_file(&file)?;
+            }
+        }
+        Ok(())
+    }
 }
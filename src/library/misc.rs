@@ -13,6 +13,7 @@ impl FromStr for LibraryFeature {
         Ok(match s {
             "none" => Self::None,
             "generate_thumbnail_at_adding" => Self::GenerateThumbnailAtAdding,
+            "detailize_at_adding" => Self::DetailizeAtAdding,
             _ => Self::None,
         })
     }
@@ -26,6 +27,7 @@ impl std::fmt::Display for LibraryFeature {
             match self {
                 Self::None => "None",
                 Self::GenerateThumbnailAtAdding => "generate_thumbnail_at_adding",
+                Self::DetailizeAtAdding => "detailize_at_adding",
             }
         )
     }
@@ -1,10 +1,26 @@
+mod detail_ops;
 mod guards;
+mod import_ops;
 mod lib_ops;
 mod media_ops;
 mod misc;
+mod query_ops;
+mod saved_search_ops;
+mod series_meta_ops;
 mod series_ops;
+mod series_order_ops;
+mod series_tree_ops;
+mod sheet_ops;
 mod summary;
+mod tag_alias_ops;
+mod tag_bulk_ops;
+mod tag_category_ops;
+mod tag_edit_ops;
+mod tag_implication_ops;
 mod tag_ops;
+mod tag_stat_ops;
+mod tag_suggest_ops;
+mod tag_tree_ops;
 mod thumbnail;
 
 type SQLite = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
@@ -38,6 +54,146 @@ pub struct Library {
     lock: super::misc::Lock,
     features: LibraryFeatures,
     thread_pool: threadpool::ThreadPool,
+    thumbnail_queue: std::sync::Arc<std::sync::Mutex<thumbnail::ThumbnailQueue>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct TagCategory {
+    pub name: String,
+    pub color: String,
+    pub sort_order: i64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub uuid: super::misc::Uuid,
+    pub caption: String,
+    pub comment: Option<String>,
+    pub media_count: usize,
+    pub category: TagCategory,
+}
+
+// Summary of bulk tag operations, media not existing are skipped and listed in missing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TagChanges {
+    pub added: usize,
+    pub removed: usize,
+    pub media: usize, // count of media changed
+    pub missing: Vec<u64>,
+}
+
+// alias is the one matched if the tag is matched by its alias.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagSearchResult {
+    pub uuid: super::misc::Uuid,
+    pub caption: String,
+    pub alias: Option<String>,
+    pub media_count: usize,
+    pub distance: usize, // edit distance, 0 for prefix or substring matching
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TagCooccurrence {
+    pub tags: (super::misc::Uuid, super::misc::Uuid),
+    pub count: usize, // count of media with both tags
+}
+
+// score is the Jaccard index of media, 1.0 for tags always appearing together.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct RelatedTag {
+    pub uuid: super::misc::Uuid,
+    pub count: usize,
+    pub score: f64,
+}
+
+// sources is count of same-folder or similar media with the tag.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct TagSuggestion {
+    pub uuid: super::misc::Uuid,
+    pub confidence: f64,
+    pub sources: usize,
+}
+
+// Filters of media, all of them must match. Empty query matches all media.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct MediaQuery {
+    #[serde(default)]
+    pub tags: Vec<super::misc::Uuid>, // media with all of the tags or their descendants
+    #[serde(default)]
+    pub exclude_tags: Vec<super::misc::Uuid>,
+    #[serde(default)]
+    pub kinds: Vec<super::media::MediaType>, // any of the kinds
+    pub time_from: Option<chrono::DateTime<chrono::Local>>, // time_add, inclusive
+    pub time_to: Option<chrono::DateTime<chrono::Local>>,   // exclusive
+    pub caption: Option<String>, // part of the caption or filename, case-insensitive
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaSort {
+    Id,
+    Filename, // natural order of the original filename
+    TimeAdd,
+    Caption,  // media without caption go last
+}
+
+// A named MediaQuery with how to show its results, options are free for the frontend.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct SavedSearch {
+    pub uuid: super::misc::Uuid,
+    pub name: String,
+    pub query: MediaQuery,
+    pub sort: MediaSort,
+    pub descending: bool,
+    pub options: serde_json::Map<String, serde_json::Value>,
+    pub time_create: chrono::DateTime<chrono::Local>,
+    pub time_modify: chrono::DateTime<chrono::Local>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeriesOrder {
+    SeriesNo,
+    Filename, // natural order of the original filename
+    TimeAdd,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Series {
+    pub uuid: super::misc::Uuid,
+    pub caption: String,
+    pub comment: Option<String>,
+    pub media_count: usize,
+    pub cover: Option<u64>,
+    pub order: SeriesOrder,
+    pub time_create: chrono::DateTime<chrono::Local>,
+    pub time_modify: chrono::DateTime<chrono::Local>,
+    pub attributes: serde_json::Map<String, serde_json::Value>,
+    pub query: Option<MediaQuery>, // members of smart series are media matching it
+    pub members: Vec<u64>,         // in the order
+}
+
+// Result of import_folder, failed files are listed with the reason.
+#[derive(Debug, Clone, Default)]
+pub struct FolderImport {
+    pub added: Vec<u64>,
+    pub existed: Vec<u64>,
+    pub skipped: Vec<String>,
+    pub failed: Vec<(String, String)>,
+    pub series: Option<super::misc::Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ThumbnailPriority {
+    Low,
+    Normal,
+    High, // e.g. visible items in grid
+}
+
+// Handle of a queued thumbnail request. Receive it by wait, or await it as a future.
+#[derive(Debug)]
+pub struct ThumbnailRequest {
+    rx: std::sync::mpsc::Receiver<super::misc::Result<Vec<u8>>>,
+    cancelled: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    waker: std::sync::Arc<std::sync::Mutex<Option<std::task::Waker>>>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
@@ -57,6 +213,7 @@ pub struct LibraryMetadata {
 pub enum LibraryFeature {
     None,
     GenerateThumbnailAtAdding,
+    DetailizeAtAdding,
 }
 
 #[derive(Debug)]
@@ -64,6 +221,55 @@ pub struct LibraryFeatures {
     features: std::collections::HashSet<LibraryFeature>,
 }
 
+// Display, FromStr and sqlite conversion of an enum stored as text, parsing is case-insensitive.
+// Texts after the first one of a variant are only accepted in parsing, e.g. Jpeg => "JPEG" | "JPG".
+#[macro_export]
+macro_rules! impl_text_enum {
+    ( $name:ident, $what:expr, {
+        $( $variant:ident => $text:literal $( | $alias:literal )* ),+ $(,)?
+    } ) => {
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", match self { $( Self::$variant => $text, )+ })
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = $crate::misc::Error;
+
+            fn from_str(s: &str) -> $crate::misc::Result<Self> {
+                Ok(match s.to_uppercase().as_str() {
+                    $( $text $( | $alias )* => Self::$variant, )+
+                    _ => {
+                        return Err($crate::misc::Error::TypeMismatch {
+                            val: s.to_string(),
+                            expect: [$( $text ),+].join(", "),
+                            found: format!("unknown {}", $what),
+                        })
+                    }
+                })
+            }
+        }
+
+        impl rusqlite::types::ToSql for $name {
+            fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+                Ok(rusqlite::types::ToSqlOutput::from(self.to_string()))
+            }
+        }
+
+        impl rusqlite::types::FromSql for $name {
+            fn column_result(
+                value: rusqlite::types::ValueRef<'_>,
+            ) -> rusqlite::types::FromSqlResult<Self> {
+                value.as_str().and_then(|s| match s.parse() {
+                    Ok(v) => Ok(v),
+                    Err(err) => Err(rusqlite::types::FromSqlError::Other(Box::new(err))),
+                })
+            }
+        }
+    };
+}
+
 #[macro_export]
 macro_rules! get_db_or_err {
     ( $db:expr ) => {
@@ -0,0 +1,115 @@
+use chrono::{DateTime, Local};
+use rusqlite::types::Value;
+
+use super::super::media::MediaType;
+use super::super::misc::{Result, Uuid};
+use super::{Library, MediaQuery};
+
+impl MediaQuery {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_tag(mut self, tag_uuid: Uuid) -> Self {
+        self.tags.push(tag_uuid);
+        self
+    }
+
+    pub fn without_tag(mut self, tag_uuid: Uuid) -> Self {
+        self.exclude_tags.push(tag_uuid);
+        self
+    }
+
+    pub fn with_kind(mut self, kind: MediaType) -> Self {
+        self.kinds.push(kind);
+        self
+    }
+
+    pub fn added_between(
+        mut self,
+        from: Option<DateTime<Local>>,
+        to: Option<DateTime<Local>>,
+    ) -> Self {
+        self.time_from = from;
+        self.time_to = to;
+        self
+    }
+
+    pub fn with_caption(mut self, caption: &str) -> Self {
+        self.caption = Some(caption.to_string());
+        self
+    }
+}
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+impl Library {
+    // Condition on table media and its parameters, "1" for the empty query.
+    pub(crate) fn media_query_sql(&self, query: &MediaQuery) -> Result<(String, Vec<Value>)> {
+        let mut conditions = vec![];
+        let mut values = vec![];
+        // tags match their descendants too
+        let mut tag_condition = |tag_uuid: &Uuid, exclude: bool| -> Result<()> {
+            let mut tags = self.get_tag_descendants(tag_uuid)?;
+            tags.push(*tag_uuid);
+            conditions.push(format!(
+                "id {} (SELECT media_id FROM media_tag_ref WHERE tag_uuid IN ({}))",
+                if exclude { "NOT IN" } else { "IN" },
+                placeholders(tags.len())
+            ));
+            values.extend(tags.iter().map(|t| Value::Text(t.to_string())));
+            Ok(())
+        };
+        for tag_uuid in &query.tags {
+            tag_condition(tag_uuid, false)?;
+        }
+        for tag_uuid in &query.exclude_tags {
+            tag_condition(tag_uuid, true)?;
+        }
+        if !query.kinds.is_empty() {
+            conditions.push(format!("type IN ({})", placeholders(query.kinds.len())));
+            values.extend(
+                query
+                    .kinds
+                    .iter()
+                    .map(|k| Value::Integer(k.get_typeid() as i64)),
+            );
+        }
+        // times are stored in UTC, compared as julian days
+        if let Some(from) = query.time_from {
+            conditions.push("julianday(time_add) >= julianday(?)".to_string());
+            values.push(Value::Text(from.naive_utc().to_string()));
+        }
+        if let Some(to) = query.time_to {
+            conditions.push("julianday(time_add) < julianday(?)".to_string());
+            values.push(Value::Text(to.naive_utc().to_string()));
+        }
+        if let Some(caption) = &query.caption {
+            conditions.push(
+                "instr(lower(IFNULL(caption, '') || char(10) || filename), lower(?)) > 0"
+                    .to_string(),
+            );
+            values.push(Value::Text(caption.clone()));
+        }
+        if conditions.is_empty() {
+            return Ok(("1".to_string(), values));
+        }
+        Ok((conditions.join(" AND "), values))
+    }
+
+    // Media matching all filters of the query, in the order of id.
+    pub fn query_media_by(&self, query: &MediaQuery) -> Result<Vec<u64>> {
+        let (condition, values) = self.media_query_sql(query)?;
+        Ok(self
+            .db
+            .get()?
+            .prepare(&format!(
+                "SELECT id FROM media WHERE {} ORDER BY id;",
+                condition
+            ))?
+            .query_map(rusqlite::params_from_iter(values), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::types::ToSql;
+use rusqlite::{params, OptionalExtension};
+
+use super::super::misc::{tools, Error, Result, Uuid};
+use super::series_meta_ops::SQL_NOW;
+use super::{Library, MediaQuery, MediaSort, SavedSearch};
+use crate::impl_text_enum;
+
+pub(crate) const SAVED_SEARCH_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS saved_search(
+        uuid CHAR(36) PRIMARY KEY NOT NULL UNIQUE,
+        name TEXT NOT NULL UNIQUE,
+        query TEXT NOT NULL, /* json of MediaQuery */
+        sort TEXT NOT NULL DEFAULT 'ID',
+        descending BOOLEAN NOT NULL DEFAULT 0,
+        options TEXT, /* json object */
+        time_create TIMESTAMP NOT NULL,
+        time_modify TIMESTAMP NOT NULL
+    );
+    ";
+
+impl_text_enum!(MediaSort, "media sort", {
+    Id => "ID",
+    Filename => "FILENAME",
+    TimeAdd => "TIME_ADD",
+    Caption => "CAPTION",
+});
+
+fn read_saved_search(row: &rusqlite::Row) -> rusqlite::Result<(SavedSearch, String)> {
+    let options: Option<String> = row.get(5)?;
+    Ok((
+        SavedSearch {
+            uuid: row.get(0)?,
+            name: row.get(1)?,
+            query: Default::default(),
+            sort: row.get(3)?,
+            descending: row.get(4)?,
+            options: options
+                .and_then(|o| serde_json::from_str(&o).ok())
+                .unwrap_or_default(),
+            time_create: row.get(6)?,
+            time_modify: row.get(7)?,
+        },
+        row.get(2)?,
+    ))
+}
+
+const SELECT_SAVED_SEARCH: &str =
+    "SELECT uuid, name, query, sort, descending, options, time_create, time_modify
+        FROM saved_search";
+
+impl Library {
+    pub fn create_saved_search(&mut self, name: String, query: &MediaQuery) -> Result<Uuid> {
+        self.saved_search_name_guard(&name, None)?;
+        let uuid = Uuid::new_v4();
+        self.db.get()?.execute(
+            &format!(
+                "INSERT INTO saved_search (uuid, name, query, time_create, time_modify)
+                    VALUES (?, ?, ?, {0}, {0});",
+                SQL_NOW
+            ),
+            params![uuid, name, serde_json::to_string(query)?],
+        )?;
+        Ok(uuid)
+    }
+
+    pub fn delete_saved_search(&mut self, uuid: &Uuid) -> Result<()> {
+        self.saved_search_exist_guard(uuid)?;
+        self.db
+            .get()?
+            .execute("DELETE FROM saved_search WHERE uuid = ?;", params![uuid])?;
+        Ok(())
+    }
+
+    pub fn get_saved_search(&self, uuid: &Uuid) -> Result<SavedSearch> {
+        self.saved_search_exist_guard(uuid)?;
+        let (mut search, query) = self.db.get()?.query_row(
+            &format!("{} WHERE uuid = ?;", SELECT_SAVED_SEARCH),
+            params![uuid],
+            read_saved_search,
+        )?;
+        search.query = serde_json::from_str(&query)?;
+        Ok(search)
+    }
+
+    // Ordered by name.
+    pub fn get_saved_searches(&self) -> Result<Vec<SavedSearch>> {
+        self.db
+            .get()?
+            .prepare(&format!("{} ORDER BY name;", SELECT_SAVED_SEARCH))?
+            .query_map(params![], read_saved_search)?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+            .into_iter()
+            .map(|(mut search, query)| {
+                search.query = serde_json::from_str(&query)?;
+                Ok(search)
+            })
+            .collect()
+    }
+
+    pub fn find_saved_search(&self, name: &str) -> Result<Option<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .query_row(
+                "SELECT uuid FROM saved_search WHERE name = ?;",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    pub fn rename_saved_search(&mut self, uuid: &Uuid, name: String) -> Result<()> {
+        self.saved_search_name_guard(&name, Some(uuid))?;
+        self.update_saved_search_column(uuid, "name", &name)
+    }
+
+    pub fn set_saved_search_query(&mut self, uuid: &Uuid, query: &MediaQuery) -> Result<()> {
+        self.update_saved_search_column(uuid, "query", &serde_json::to_string(query)?)
+    }
+
+    pub fn set_saved_search_sort(
+        &mut self,
+        uuid: &Uuid,
+        sort: MediaSort,
+        descending: bool,
+    ) -> Result<()> {
+        self.saved_search_exist_guard(uuid)?;
+        self.db.get()?.execute(
+            &format!(
+                "UPDATE saved_search SET sort = ?, descending = ?, time_modify = {}
+                    WHERE uuid = ?;",
+                SQL_NOW
+            ),
+            params![sort, descending, uuid],
+        )?;
+        Ok(())
+    }
+
+    // Null value removes the option.
+    pub fn set_saved_search_option(
+        &mut self,
+        uuid: &Uuid,
+        key: &str,
+        value: serde_json::Value,
+    ) -> Result<()> {
+        let mut options = self.get_saved_search(uuid)?.options;
+        match value {
+            serde_json::Value::Null => options.remove(key),
+            value => options.insert(key.to_string(), value),
+        };
+        self.update_saved_search_column(uuid, "options", &serde_json::to_string(&options)?)
+    }
+
+    // Media matching the query in the sort of the saved search.
+    pub fn run_saved_search(&self, uuid: &Uuid) -> Result<Vec<u64>> {
+        let search = self.get_saved_search(uuid)?;
+        let (condition, values) = self.media_query_sql(&search.query)?;
+        let dir = if search.descending { "DESC" } else { "ASC" };
+        let order_by = match search.sort {
+            MediaSort::TimeAdd => format!("time_add {0}, id {0}", dir),
+            MediaSort::Caption => format!("caption IS NULL, caption {0}, id {0}", dir),
+            _ => format!("id {}", dir), // sorted below for Filename
+        };
+        let mut media: Vec<(u64, String)> = self
+            .db
+            .get()?
+            .prepare(&format!(
+                "SELECT id, filename FROM media WHERE {} ORDER BY {};",
+                condition, order_by
+            ))?
+            .query_map(rusqlite::params_from_iter(values), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if let MediaSort::Filename = search.sort {
+            media.sort_by(|a, b| tools::natural_cmp(&a.1, &b.1));
+            if search.descending {
+                media.reverse();
+            }
+        }
+        Ok(media.into_iter().map(|(id, _)| id).collect())
+    }
+
+    // JSON array of all saved searches, queries keep the tag uuids of this library.
+    // The library has no backup or export of its own yet; saved searches live in the library
+    // database, so a copy of the library folder carries them, and this json is the portable form.
+    pub fn export_saved_searches(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.get_saved_searches()?)?)
+    }
+
+    // Searches with the same uuid are replaced. Names must be unique among the searches after the
+    // import, otherwise the whole import fails.
+    pub fn import_saved_searches(&mut self, json: &str) -> Result<usize> {
+        let searches: Vec<SavedSearch> = serde_json::from_str(json)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let mut names = tx
+            .prepare("SELECT uuid, name FROM saved_search;")?
+            .query_map([], |row| {
+                Ok((row.get::<_, Uuid>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        for search in &searches {
+            names.insert(search.uuid, search.name.clone());
+        }
+        let mut seen = HashSet::new();
+        for name in names.values() {
+            if !seen.insert(name) {
+                return Err(Error::AlreadyExists(format!(
+                    "Saved search with name {} already exists.",
+                    name
+                )));
+            }
+        }
+        // replaced rows go first, names may move between searches
+        for search in &searches {
+            tx.execute(
+                "DELETE FROM saved_search WHERE uuid = ?;",
+                params![search.uuid],
+            )?;
+        }
+        for search in &searches {
+            tx.execute(
+                "INSERT INTO saved_search
+                    (uuid, name, query, sort, descending, options, time_create, time_modify)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?);",
+                params![
+                    search.uuid,
+                    search.name,
+                    serde_json::to_string(&search.query)?,
+                    search.sort,
+                    search.descending,
+                    serde_json::to_string(&search.options)?,
+                    search.time_create,
+                    search.time_modify
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(searches.len())
+    }
+
+    fn saved_search_name_guard(&self, name: &str, uuid: Option<&Uuid>) -> Result<()> {
+        match self.find_saved_search(name)? {
+            Some(existed) if Some(&existed) != uuid => Err(Error::AlreadyExists(format!(
+                "Saved search with name {} already exists.",
+                name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn update_saved_search_column(
+        &mut self,
+        uuid: &Uuid,
+        column: &str,
+        value: &dyn ToSql,
+    ) -> Result<()> {
+        self.saved_search_exist_guard(uuid)?;
+        self.db.get()?.execute(
+            &format!(
+                "UPDATE saved_search SET {} = ?, time_modify = {} WHERE uuid = ?;",
+                column, SQL_NOW
+            ),
+            params![value, uuid],
+        )?;
+        Ok(())
+    }
+}
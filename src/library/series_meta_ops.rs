@@ -0,0 +1,302 @@
+use rusqlite::params;
+use rusqlite::types::{ToSql, Value};
+
+use super::super::misc::{tools, Error, Result, Uuid};
+use super::series_ops::{insert_series, insert_series_member};
+use super::{Library, MediaQuery, SQLite, Series, SeriesOrder};
+use crate::impl_text_enum;
+
+pub(crate) const SQL_NOW: &str = "STRFTIME('%Y-%m-%d %H:%M:%f+00:00', 'NOW')";
+
+// Columns added to series after the first release.
+pub(crate) fn migrate_series(db: &SQLite) -> Result<()> {
+    let db = db.get()?;
+    let columns = [
+        ("cover_id", "INTEGER"),
+        ("order_mode", "TEXT NOT NULL DEFAULT 'SERIES_NO'"),
+        ("time_create", "TIMESTAMP"),
+        ("time_modify", "TIMESTAMP"),
+        ("attributes", "TEXT"),
+        ("query", "TEXT"),
+    ];
+    for (column, definition) in columns {
+        if !tools::has_column(&db, "series", column)? {
+            db.execute(
+                &format!("ALTER TABLE series ADD COLUMN {} {};", column, definition),
+                params![],
+            )?;
+        }
+    }
+    // default of ALTER TABLE must be constant
+    db.execute(
+        &format!(
+            "UPDATE series SET time_create = IFNULL(time_create, {0}),
+                time_modify = IFNULL(time_modify, {0});",
+            SQL_NOW
+        ),
+        params![],
+    )?;
+    Ok(())
+}
+
+// Tables keeping a MediaQuery json in their query column.
+const QUERY_TABLES: [&str; 2] = ["series", "saved_search"];
+
+// Tag uuids in stored queries follow a merged tag into another, or are removed with None.
+pub(crate) fn repoint_query_tag(
+    db: &rusqlite::Connection,
+    from: &Uuid,
+    into: Option<&Uuid>,
+) -> Result<()> {
+    for table in QUERY_TABLES {
+        let queries: Vec<(Uuid, String)> = db
+            .prepare(&format!(
+                "SELECT uuid, query FROM {} WHERE query LIKE '%' || ? || '%';",
+                table
+            ))?
+            .query_map(params![from], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (uuid, query) in queries {
+            let mut query: MediaQuery = serde_json::from_str(&query)?;
+            for tags in [&mut query.tags, &mut query.exclude_tags] {
+                let mut repointed: Vec<Uuid> = vec![];
+                for tag in tags.drain(..) {
+                    let tag = match into {
+                        _ if tag != *from => tag,
+                        Some(into) => *into,
+                        None => continue,
+                    };
+                    if !repointed.contains(&tag) {
+                        repointed.push(tag);
+                    }
+                }
+                *tags = repointed;
+            }
+            db.execute(
+                &format!("UPDATE {} SET query = ? WHERE uuid = ?;", table),
+                params![serde_json::to_string(&query)?, uuid],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn touch_series(db: &rusqlite::Connection, uuid: &Uuid) -> Result<()> {
+    db.execute(
+        &format!(
+            "UPDATE series SET time_modify = {} WHERE uuid = ?;",
+            SQL_NOW
+        ),
+        params![uuid],
+    )?;
+    Ok(())
+}
+
+impl_text_enum!(SeriesOrder, "series order", {
+    SeriesNo => "SERIES_NO",
+    Filename => "FILENAME",
+    TimeAdd => "TIME_ADD",
+});
+
+impl Library {
+    // Members in the order mode of the series. Unsorted media go last for SeriesNo.
+    // Members of smart series are evaluated from the query on every call, in the order of id for SeriesNo.
+    pub fn get_series_members(&self, uuid: &Uuid) -> Result<Vec<u64>> {
+        let query = self.get_series_query(uuid)?;
+        let db = self.db.get()?;
+        let order: SeriesOrder = db.query_row(
+            "SELECT order_mode FROM series WHERE uuid = ?;",
+            params![uuid],
+            |row| row.get(0),
+        )?;
+        let (source, values) = match &query {
+            Some(query) => {
+                let (condition, values) = self.media_query_sql(query)?;
+                (format!("media WHERE {}", condition), values)
+            }
+            None => (
+                "media_series_ref JOIN media ON media.id = media_series_ref.media_id
+                    WHERE series_uuid = ?"
+                    .to_string(),
+                vec![Value::Text(uuid.to_string())],
+            ),
+        };
+        let order_by = match order {
+            SeriesOrder::SeriesNo if query.is_none() => "series_no IS NULL, series_no, media.id",
+            SeriesOrder::TimeAdd => "media.time_add, media.id",
+            _ => "media.id", // sorted below for Filename
+        };
+        let mut members: Vec<(u64, String)> = db
+            .prepare(&format!(
+                "SELECT media.id, media.filename FROM {} ORDER BY {};",
+                source, order_by
+            ))?
+            .query_map(rusqlite::params_from_iter(values), |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if let SeriesOrder::Filename = order {
+            members.sort_by(|a, b| tools::natural_cmp(&a.1, &b.1));
+        }
+        Ok(members.into_iter().map(|(id, _)| id).collect())
+    }
+
+    // Cover falls back to the first member if not set or not in the series any more.
+    pub fn get_series(&self, uuid: &Uuid) -> Result<Series> {
+        let members = self.get_series_members(uuid)?;
+        let mut series = self.db.get()?.query_row(
+            "SELECT caption, comment, media_count, cover_id, order_mode,
+                    time_create, time_modify, attributes
+                FROM series WHERE uuid = ?;",
+            params![uuid],
+            |row| {
+                let attributes: Option<String> = row.get(7)?;
+                Ok(Series {
+                    uuid: *uuid,
+                    caption: row.get(0)?,
+                    comment: row.get(1)?,
+                    media_count: row.get(2)?,
+                    cover: row.get(3)?,
+                    order: row.get(4)?,
+                    time_create: row.get(5)?,
+                    time_modify: row.get(6)?,
+                    attributes: attributes
+                        .and_then(|a| serde_json::from_str(&a).ok())
+                        .unwrap_or_default(),
+                    query: None,
+                    members: vec![],
+                })
+            },
+        )?;
+        series.query = self.get_series_query(uuid)?;
+        if series.query.is_some() {
+            series.media_count = members.len();
+        }
+        if !matches!(series.cover, Some(cover) if members.contains(&cover)) {
+            series.cover = members.first().copied();
+        }
+        series.members = members;
+        Ok(series)
+    }
+
+    // Smart series share the uuid space with static ones, but media could not be added to them.
+    pub fn create_smart_series(&mut self, caption: String, query: &MediaQuery) -> Result<Uuid> {
+        let uuid = self.create_series(caption, None)?;
+        self.set_series_query(&uuid, Some(query))?;
+        Ok(uuid)
+    }
+
+    // None turns a smart series into an empty static one, see snapshot_series to keep the members.
+    pub fn set_series_query(&mut self, uuid: &Uuid, query: Option<&MediaQuery>) -> Result<()> {
+        if query.is_some() && self.get_series_query(uuid)?.is_none() {
+            let count: usize = self.db.get()?.query_row(
+                "SELECT COUNT(*) FROM media_series_ref WHERE series_uuid = ?;",
+                params![uuid],
+                |row| row.get(0),
+            )?;
+            if count > 0 {
+                return Err(Error::Occupied(format!(
+                    "Series {} with {} media",
+                    uuid, count
+                )));
+            }
+        }
+        let query = query.map(serde_json::to_string).transpose()?;
+        self.update_series_column(uuid, "query", &query)
+    }
+
+    pub fn get_series_query(&self, uuid: &Uuid) -> Result<Option<MediaQuery>> {
+        self.series_exist_guard(uuid)?;
+        let query: Option<String> = self.db.get()?.query_row(
+            "SELECT query FROM series WHERE uuid = ?;",
+            params![uuid],
+            |row| row.get(0),
+        )?;
+        Ok(query.map(|q| serde_json::from_str(&q)).transpose()?)
+    }
+
+    // Copy current members into a new static series numbered in the order, with the same order mode.
+    pub fn snapshot_series(&mut self, uuid: &Uuid, caption: String) -> Result<Uuid> {
+        let series = self.get_series(uuid)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let snapshot = insert_series(&tx, caption, series.comment)?;
+        for (i, id) in series.members.iter().enumerate() {
+            insert_series_member(&tx, *id, &snapshot, Some(i as u64 + 1), false)?;
+        }
+        tx.execute(
+            "UPDATE series SET order_mode = ?, attributes = ? WHERE uuid = ?;",
+            params![
+                SeriesOrder::SeriesNo,
+                serde_json::to_string(&series.attributes)?,
+                snapshot
+            ],
+        )?;
+        tx.commit()?;
+        self.summary.series_count += 1;
+        Ok(snapshot)
+    }
+
+    pub fn set_series_caption(&mut self, uuid: &Uuid, caption: String) -> Result<()> {
+        self.update_series_column(uuid, "caption", &caption)
+    }
+
+    pub fn set_series_comment(&mut self, uuid: &Uuid, comment: Option<String>) -> Result<()> {
+        self.update_series_column(uuid, "comment", &comment)
+    }
+
+    // None to use the first member.
+    pub fn set_series_cover(&mut self, uuid: &Uuid, id: Option<u64>) -> Result<()> {
+        if let Some(id) = id {
+            self.series_member_guard(id, uuid)?;
+        }
+        self.update_series_column(uuid, "cover_id", &id)
+    }
+
+    pub fn set_series_order(&mut self, uuid: &Uuid, order: SeriesOrder) -> Result<()> {
+        self.update_series_column(uuid, "order_mode", &order)
+    }
+
+    pub fn get_series_attributes(
+        &self,
+        uuid: &Uuid,
+    ) -> Result<serde_json::Map<String, serde_json::Value>> {
+        self.series_exist_guard(uuid)?;
+        let attributes: Option<String> = self.db.get()?.query_row(
+            "SELECT attributes FROM series WHERE uuid = ?;",
+            params![uuid],
+            |row| row.get(0),
+        )?;
+        Ok(match attributes {
+            Some(attributes) => serde_json::from_str(&attributes)?,
+            None => Default::default(),
+        })
+    }
+
+    // Null value removes the attribute.
+    pub fn set_series_attribute(
+        &mut self,
+        uuid: &Uuid,
+        key: &str,
+        value: serde_json::Value,
+    ) -> Result<()> {
+        let mut attributes = self.get_series_attributes(uuid)?;
+        match value {
+            serde_json::Value::Null => attributes.remove(key),
+            value => attributes.insert(key.to_string(), value),
+        };
+        self.update_series_column(uuid, "attributes", &serde_json::to_string(&attributes)?)
+    }
+
+    fn update_series_column(&mut self, uuid: &Uuid, column: &str, value: &dyn ToSql) -> Result<()> {
+        self.series_exist_guard(uuid)?;
+        self.db.get()?.execute(
+            &format!(
+                "UPDATE series SET {} = ?, time_modify = {} WHERE uuid = ?;",
+                column, SQL_NOW
+            ),
+            params![value, uuid],
+        )?;
+        Ok(())
+    }
+}
@@ -1,16 +1,75 @@
 use rusqlite::params;
 
 use super::super::misc::{Error, Result, Uuid};
+use super::series_meta_ops::{touch_series, SQL_NOW};
+use super::series_order_ops::series_numbers;
 use super::Library;
 
+// Insert an empty series, series_count of summary is left to the caller.
+pub(crate) fn insert_series(
+    db: &rusqlite::Connection,
+    caption: String,
+    comment: Option<String>,
+) -> Result<Uuid> {
+    let uuid = Uuid::new_v4();
+    db.execute(
+        &format!(
+            "INSERT INTO series (uuid, caption, comment, media_count, time_create, time_modify)
+                VALUES (?, ?, ?, 0, {0}, {0});",
+            SQL_NOW
+        ),
+        params![uuid, caption, comment],
+    )?;
+    Ok(uuid)
+}
+
+// Add the media to the static series, see add_to_series.
+pub(crate) fn insert_series_member(
+    db: &rusqlite::Connection,
+    id: u64,
+    uuid: &Uuid,
+    no: Option<u64>,
+    unsorted: bool,
+) -> Result<()> {
+    Library::series_member_not_exist_guard(db, id, uuid)?;
+    let to_check = series_numbers(db, uuid, Some(id))?;
+    let no = if let Some(no) = no {
+        // if the no is specified.
+        if to_check.iter().any(|i| *i == no) {
+            return Err(Error::Occupied(format!(
+                "occupied when add media(id {}) to series {} with no {}",
+                id, uuid, no
+            )));
+        }
+        Some(no)
+    } else {
+        // or this is a unsorted media
+        if unsorted {
+            None
+        } else {
+            // or not, we use the biggest no in the to_check list +1 to be the no
+            let biggest = to_check.iter().max();
+            Some(match biggest {
+                Some(m) => m + 1,
+                None => 1,
+            })
+        }
+    };
+    db.execute(
+        "INSERT INTO media_series_ref (media_id, series_uuid, series_no) VALUES (?, ?, ?)",
+        params![id, uuid, no],
+    )?;
+    db.execute(
+        "UPDATE series SET media_count = media_count + 1 WHERE uuid = ?;",
+        params![uuid],
+    )?;
+    touch_series(db, uuid)
+}
+
 impl Library {
     pub fn create_series(&mut self, caption: String, comment: Option<String>) -> Result<Uuid> {
         let db = self.db.get()?;
-        let uuid = Uuid::new_v4();
-        db.execute(
-            "INSERT INTO series (uuid, caption, comment, media_count) VALUES (?, ?, ?, 0);",
-            params![uuid, caption, comment],
-        )?;
+        let uuid = insert_series(&db, caption, comment)?;
         self.summary.series_count += 1;
         Ok(uuid)
     }
@@ -21,7 +80,13 @@ impl Library {
             "DELETE FROM media_series_ref WHERE series_uuid = ?;",
             params![uuid],
         )?;
+        // children become top-level series
+        db.execute(
+            "DELETE FROM series_series_ref WHERE parent_uuid = ?1 OR child_uuid = ?1;",
+            params![uuid],
+        )?;
         db.execute("DELETE FROM series WHERE uuid = ?;", params![uuid])?;
+        self.remove_series_sheets(uuid)?;
         self.summary.series_count -= 1;
         Ok(())
     }
@@ -53,58 +118,32 @@ impl Library {
         no: Option<u64>,
         unsorted: bool,
     ) -> Result<()> {
-        let db = self.db.get()?;
-        let mut stmt = db.prepare(
-            "SELECT series_no FROM media_series_ref WHERE series_uuid = ?1 AND media_id != ?2;",
-        )?;
-        let iter = stmt.query_map(params![uuid, id], |row| row.get(0))?;
-        let to_check: Vec<u64> = iter.map(|x| x.unwrap()).collect();
-        let no = if let Some(no) = no {
-            // if the no is specified.
-            if to_check.iter().any(|i| *i == no) {
-                return Err(Error::Occupied(format!(
-                    "occupied when add media(id {}) to series {} with no {}",
-                    id, uuid, no
-                )));
-            }
-            Some(no)
-        } else {
-            // or this is a unsorted media
-            if unsorted {
-                None
-            } else {
-                // or not, we use the biggest no in the to_check list +1 to be the no
-                let biggest = to_check.iter().max();
-                Some(match biggest {
-                    Some(m) => m + 1,
-                    None => 1,
-                })
-            }
-        };
-        db.execute(
-            "INSERT INTO media_series_ref (media_id, series_uuid, series_no) VALUES (?, ?, ?)",
-            params![id, uuid, no],
-        )?;
-        db.execute(
-            "UPDATE series SET media_count = media_count + 1 WHERE uuid = ?;",
-            params![uuid],
-        )?;
+        self.static_series_guard(uuid)?;
+        self.media_exist_guard(id)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        insert_series_member(&tx, id, uuid, no, unsorted)?;
+        tx.commit()?;
         Ok(())
     }
 
     pub fn remove_from_series(&mut self, id: u64, uuid: &Uuid) -> Result<()> {
-        let db = self.db.get()?;
-        db.execute(
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let removed = tx.execute(
             "DELETE FROM media_series_ref WHERE media_id = ? AND series_uuid = ?;",
             params![id, uuid],
         )?;
-        db.execute(
-            "UPDATE series SET media_count = media_count - 1 WHERE uuid = ?;",
-            params![uuid],
+        tx.execute(
+            "UPDATE series SET media_count = media_count - ? WHERE uuid = ?;",
+            params![removed, uuid],
         )?;
+        touch_series(&tx, uuid)?;
+        tx.commit()?;
         Ok(())
     }
 
+    // With insert, media at and after the no are shifted by one, or it fails if the no is occupied.
     pub fn update_series_no(
         &mut self,
         id: u64,
@@ -112,12 +151,10 @@ impl Library {
         no: u64,
         insert: bool,
     ) -> Result<()> {
-        let db = self.db.get()?;
-        let mut stmt = db.prepare(
-            "SELECT series_no FROM media_series_ref WHERE series_uuid = ?1 AND media_id != ?2;",
-        )?;
-        let iter = stmt.query_map(params![series_uuid, id], |row| row.get(0))?;
-        let to_check: Vec<u64> = iter.map(|x| x.unwrap()).collect();
+        self.series_member_guard(id, series_uuid)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let to_check = series_numbers(&tx, series_uuid, Some(id))?;
         if to_check.iter().any(|i| *i == no) {
             // insert or error
             if !insert {
@@ -126,38 +163,24 @@ impl Library {
                     id, series_uuid, no
                 )));
             }
-            db.execute(
-                "UPDATE media_series_ref SET series_no = series_no + 1 WHERE series_uuid = ? AND series_no >= ?;",
-                params![series_uuid, no],
+            tx.execute(
+                "UPDATE media_series_ref SET series_no = series_no + 1
+                    WHERE series_uuid = ? AND series_no >= ? AND media_id != ?;",
+                params![series_uuid, no, id],
             )?;
         }
-        db.execute(
-            "UPDATE media_series_ref SET series_no = ? WHERE media_id = ?;",
-            params![no, id],
+        tx.execute(
+            "UPDATE media_series_ref SET series_no = ? WHERE media_id = ? AND series_uuid = ?;",
+            params![no, id, series_uuid],
         )?;
+        touch_series(&tx, series_uuid)?;
+        tx.commit()?;
         Ok(())
     }
 
+    // Same as compact_series_no.
     pub fn trim_series_no(&mut self, uuid: &Uuid) -> Result<()> {
-        // I sincerely recommend you not to use this function as much as possible
-        let db = self.db.get()?;
-        let mut ids: Vec<(u64, u64)> = db
-            .prepare("SELECT media_id, series_no FROM media_series_ref WHERE series_uuid = ?;")?
-            .query_map(params![uuid], |row| Ok((row.get(0)?, row.get(1)?)))?
-            .map(|x| x.unwrap())
-            .collect();
-        ids.sort_by(|a, b| a.1.cmp(&b.1));
-        ids[0].1 = 1;
-        for i in 1..ids.len() {
-            ids[i].1 = ids[i - 1].1 + 1;
-        }
-        for (id, no) in ids {
-            db.execute(
-                "UPDATE media_series_ref SET series_no = ? WHERE media_id = ?;",
-                params![no, id],
-            )?;
-        }
-        Ok(())
+        self.compact_series_no(uuid)
     }
 
     pub fn query_series(&self, sql_stmt: &str) -> Result<Vec<Uuid>> {
@@ -0,0 +1,138 @@
+use rusqlite::params;
+
+use super::super::misc::{Error, Result, Uuid};
+use super::series_meta_ops::touch_series;
+use super::Library;
+
+// Numbers used in the series, unsorted media are not counted.
+pub(crate) fn series_numbers(
+    db: &rusqlite::Connection,
+    uuid: &Uuid,
+    except: Option<u64>,
+) -> Result<Vec<u64>> {
+    Ok(db
+        .prepare(
+            "SELECT series_no FROM media_series_ref
+                WHERE series_uuid = ?1 AND media_id != IFNULL(?2, -1) AND series_no IS NOT NULL;",
+        )?
+        .query_map(params![uuid, except], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+// Media with numbers in the order of the numbers.
+fn numbered_members(db: &rusqlite::Connection, uuid: &Uuid) -> Result<Vec<u64>> {
+    Ok(db
+        .prepare(
+            "SELECT media_id FROM media_series_ref
+                WHERE series_uuid = ? AND series_no IS NOT NULL ORDER BY series_no, media_id;",
+        )?
+        .query_map(params![uuid], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?)
+}
+
+// Number media from 1 in the given order.
+fn renumber(db: &rusqlite::Connection, uuid: &Uuid, ids: &[u64]) -> Result<()> {
+    let mut stmt = db.prepare(
+        "UPDATE media_series_ref SET series_no = ? WHERE series_uuid = ? AND media_id = ?;",
+    )?;
+    for (i, id) in ids.iter().enumerate() {
+        stmt.execute(params![i as u64 + 1, uuid, id])?;
+    }
+    touch_series(db, uuid)
+}
+
+impl Library {
+    // Move the media to the position counted from 1, others are renumbered without gaps.
+    // Unsorted media become sorted.
+    pub fn move_in_series(&mut self, id: u64, uuid: &Uuid, position: u64) -> Result<()> {
+        self.static_series_guard(uuid)?;
+        self.series_member_guard(id, uuid)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let mut ids = numbered_members(&tx, uuid)?;
+        ids.retain(|v| *v != id);
+        let index = (position.max(1) as usize - 1).min(ids.len());
+        ids.insert(index, id);
+        renumber(&tx, uuid, &ids)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn swap_in_series(&mut self, uuid: &Uuid, a: u64, b: u64) -> Result<()> {
+        self.static_series_guard(uuid)?;
+        self.series_member_guard(a, uuid)?;
+        self.series_member_guard(b, uuid)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let (a_no, b_no): (Option<u64>, Option<u64>) = {
+            let mut stmt = tx.prepare(
+                "SELECT series_no FROM media_series_ref WHERE series_uuid = ? AND media_id = ?;",
+            )?;
+            (
+                stmt.query_row(params![uuid, a], |row| row.get(0))?,
+                stmt.query_row(params![uuid, b], |row| row.get(0))?,
+            )
+        };
+        let update =
+            "UPDATE media_series_ref SET series_no = ? WHERE series_uuid = ? AND media_id = ?;";
+        tx.execute(update, params![b_no, uuid, a])?;
+        tx.execute(update, params![a_no, uuid, b])?;
+        touch_series(&tx, uuid)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Number all members in the order of ids, which must have all members exactly once.
+    pub fn reorder_series(&mut self, uuid: &Uuid, ids: &[u64]) -> Result<()> {
+        self.static_series_guard(uuid)?;
+        let mut members = self.get_series_members(uuid)?;
+        let mut sorted = ids.to_vec();
+        members.sort_unstable();
+        sorted.sort_unstable();
+        if members != sorted {
+            return Err(Error::NotMatch(format!("Members of series {}", uuid)));
+        }
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        renumber(&tx, uuid, ids)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Add the media with the no, media at and after the no are shifted by one.
+    pub fn insert_into_series(&mut self, id: u64, uuid: &Uuid, no: u64) -> Result<()> {
+        self.static_series_guard(uuid)?;
+        self.media_exist_guard(id)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        Self::series_member_not_exist_guard(&tx, id, uuid)?;
+        tx.execute(
+            "UPDATE media_series_ref SET series_no = series_no + 1
+                WHERE series_uuid = ? AND series_no >= ?;",
+            params![uuid, no],
+        )?;
+        tx.execute(
+            "INSERT INTO media_series_ref (media_id, series_uuid, series_no) VALUES (?, ?, ?)",
+            params![id, uuid, no],
+        )?;
+        tx.execute(
+            "UPDATE series SET media_count = media_count + 1 WHERE uuid = ?;",
+            params![uuid],
+        )?;
+        touch_series(&tx, uuid)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Renumber from 1 keeping the order and removing gaps, unsorted media stay unsorted.
+    pub fn compact_series_no(&mut self, uuid: &Uuid) -> Result<()> {
+        self.static_series_guard(uuid)?;
+        self.series_exist_guard(uuid)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let ids = numbered_members(&tx, uuid)?;
+        renumber(&tx, uuid, &ids)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use rusqlite::params;
+
+use super::super::misc::{Error, Result, Uuid};
+use super::series_meta_ops::touch_series;
+use super::Library;
+
+// A series is in at most one parent, e.g. chapter in volume in collection.
+pub(crate) const SERIES_CHILD_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS series_series_ref(
+        id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+        parent_uuid CHAR(36) NOT NULL,
+        child_uuid CHAR(36) NOT NULL UNIQUE,
+        series_no INTEGER,
+        FOREIGN KEY(parent_uuid) REFERENCES series(uuid),
+        FOREIGN KEY(child_uuid) REFERENCES series(uuid)
+    );
+    ";
+
+impl Library {
+    // Appended after the other children if no is None.
+    pub fn add_child_series(&mut self, parent: &Uuid, child: &Uuid, no: Option<u64>) -> Result<()> {
+        self.series_exist_guard(parent)?;
+        self.series_exist_guard(child)?;
+        if parent == child || self.get_series_path(parent)?.contains(child) {
+            return Err(Error::Cycle(format!(
+                "Series {} in its descendant {}",
+                child, parent
+            )));
+        }
+        if let Some(current) = self.get_parent_series(child)? {
+            return Err(Error::Occupied(format!(
+                "Series {} in series {}",
+                child, current
+            )));
+        }
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let no = match no {
+            Some(no) => no,
+            None => tx.query_row(
+                "SELECT IFNULL(MAX(series_no), 0) + 1 FROM series_series_ref WHERE parent_uuid = ?;",
+                params![parent],
+                |row| row.get(0),
+            )?,
+        };
+        tx.execute(
+            "INSERT INTO series_series_ref (parent_uuid, child_uuid, series_no) VALUES (?, ?, ?);",
+            params![parent, child, no],
+        )?;
+        touch_series(&tx, parent)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_child_series(&mut self, parent: &Uuid, child: &Uuid) -> Result<()> {
+        self.series_exist_guard(parent)?;
+        self.series_exist_guard(child)?;
+        let db = self.db.get()?;
+        let removed = db.execute(
+            "DELETE FROM series_series_ref WHERE parent_uuid = ? AND child_uuid = ?;",
+            params![parent, child],
+        )?;
+        if removed == 0 {
+            return Err(Error::NotIn {
+                a: format!("Series {}", child),
+                b: format!("series {}", parent),
+            });
+        }
+        touch_series(&db, parent)?;
+        Ok(())
+    }
+
+    // Number all children in the order of children, which must have all children exactly once.
+    pub fn reorder_child_series(&mut self, parent: &Uuid, children: &[Uuid]) -> Result<()> {
+        let mut current = self.get_child_series(parent)?;
+        let mut sorted = children.to_vec();
+        current.sort_unstable();
+        sorted.sort_unstable();
+        if current != sorted {
+            return Err(Error::NotMatch(format!("Children of series {}", parent)));
+        }
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        for (i, child) in children.iter().enumerate() {
+            tx.execute(
+                "UPDATE series_series_ref SET series_no = ? WHERE parent_uuid = ? AND child_uuid = ?;",
+                params![i as u64 + 1, parent, child],
+            )?;
+        }
+        touch_series(&tx, parent)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_child_series(&self, parent: &Uuid) -> Result<Vec<Uuid>> {
+        self.series_exist_guard(parent)?;
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "SELECT child_uuid FROM series_series_ref WHERE parent_uuid = ?
+                    ORDER BY series_no IS NULL, series_no, id;",
+            )?
+            .query_map(params![parent], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn get_parent_series(&self, child: &Uuid) -> Result<Option<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .query_row(
+                "SELECT parent_uuid FROM series_series_ref WHERE child_uuid = ?;",
+                params![child],
+                |row| row.get(0),
+            )
+            .or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                _ => Err(e),
+            })?)
+    }
+
+    // From the top-level series down to the series itself.
+    pub fn get_series_path(&self, uuid: &Uuid) -> Result<Vec<Uuid>> {
+        self.series_exist_guard(uuid)?;
+        let mut path = vec![*uuid];
+        while let Some(parent) = self.get_parent_series(path.last().unwrap())? {
+            if path.contains(&parent) {
+                break; // broken data, never loop
+            }
+            path.push(parent);
+        }
+        path.reverse();
+        Ok(path)
+    }
+
+    // Media of the series in its order, then media of children one by one, recursively.
+    pub fn get_series_flattened(&self, uuid: &Uuid) -> Result<Vec<u64>> {
+        let mut media = vec![];
+        let mut visited = vec![];
+        self.flatten_series(uuid, &mut media, &mut visited)?;
+        Ok(media)
+    }
+
+    fn flatten_series(
+        &self,
+        uuid: &Uuid,
+        media: &mut Vec<u64>,
+        visited: &mut Vec<Uuid>,
+    ) -> Result<()> {
+        if visited.contains(uuid) {
+            return Ok(());
+        }
+        visited.push(*uuid);
+        media.extend(self.get_series_members(uuid)?);
+        for child in self.get_child_series(uuid)? {
+            self.flatten_series(&child, media, visited)?;
+        }
+        Ok(())
+    }
+
+    // Previous and next media of the media in the flattened series, across boundaries of children.
+    pub fn get_series_neighbours(
+        &self,
+        uuid: &Uuid,
+        id: u64,
+    ) -> Result<(Option<u64>, Option<u64>)> {
+        let media = self.get_series_flattened(uuid)?;
+        let index = media
+            .iter()
+            .position(|v| *v == id)
+            .ok_or_else(|| Error::NotIn {
+                a: format!("Media {}", id),
+                b: format!("series {}", uuid),
+            })?;
+        Ok((
+            index.checked_sub(1).map(|i| media[i]),
+            media.get(index + 1).copied(),
+        ))
+    }
+}
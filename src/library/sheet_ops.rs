@@ -0,0 +1,242 @@
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont};
+use image::{GenericImage, Rgb, RgbImage};
+use rusqlite::{params, OptionalExtension};
+
+use super::super::media::{ThumbnailFormat, ThumbnailSpec};
+use super::super::misc::{Error, Result, Uuid};
+use super::series_meta_ops::SQL_NOW;
+use super::thumbnail::evict_over_budget;
+use super::{Library, ThumbnailPriority};
+
+// Sheets share the budget and LRU eviction of thumbnails.
+pub(crate) const SERIES_SHEET_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS series_sheet(
+        id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+        series_uuid CHAR(36) NOT NULL,
+        columns INTEGER NOT NULL,
+        tile_size INTEGER NOT NULL,
+        captions BOOLEAN NOT NULL,
+        signature TEXT NOT NULL, /* hash of members, sheet is outdated once it changed */
+        image BLOB,
+        size INTEGER NOT NULL,
+        time_access TIMESTAMP NOT NULL,
+        UNIQUE(series_uuid, columns, tile_size, captions)
+    );
+    ";
+
+const SHEET_GAP: u32 = 4;
+const SHEET_BACKGROUND: Rgb<u8> = Rgb([32, 32, 32]);
+const TILE_BACKGROUND: Rgb<u8> = Rgb([64, 64, 64]);
+const CAPTION_COLOR: Rgb<u8> = Rgb([224, 224, 224]);
+const MIN_TILE_SIZE: u32 = 16;
+const MIN_CAPTION_HEIGHT: u32 = 12;
+
+// Draw text in one line from the left top, glyphs out of the box are cut.
+fn draw_caption(
+    sheet: &mut RgbImage,
+    font: &FontVec,
+    text: &str,
+    x: u32,
+    y: u32,
+    size: (u32, u32),
+) {
+    let scale = PxScale::from(size.1 as f32);
+    let font = font.as_scaled(scale);
+    let baseline = y as f32 + font.ascent();
+    let (mut caret, mut last) = (x as f32, None);
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(last) = last {
+            caret += font.kern(last, id);
+        }
+        let glyph = id.with_scale_and_position(scale, ab_glyph::point(caret, baseline));
+        caret += font.h_advance(id);
+        last = Some(id);
+        if let Some(outlined) = font.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|dx, dy, coverage| {
+                let (px, py) = (
+                    bounds.min.x as i64 + dx as i64,
+                    bounds.min.y as i64 + dy as i64,
+                );
+                if px < x as i64
+                    || py < y as i64
+                    || px >= (x + size.0) as i64
+                    || py >= (y + size.1) as i64
+                {
+                    return;
+                }
+                let pixel = sheet.get_pixel_mut(px as u32, py as u32);
+                for (channel, color) in pixel.0.iter_mut().zip(CAPTION_COLOR.0) {
+                    *channel = (*channel as f32 * (1.0 - coverage) + color as f32 * coverage) as u8;
+                }
+            });
+        }
+        if caret >= (x + size.0) as f32 {
+            break;
+        }
+    }
+}
+
+impl Library {
+    // Compose thumbnails of series members in the order of get_series_members.
+    // With caption_font, a TrueType or OpenType font file, caption or filename of each media is
+    // drawn under its tile. Returns JPEG bytes, which are cached until members of the series changed.
+    pub fn render_series_sheet(
+        &mut self,
+        series_uuid: &Uuid,
+        columns: u32,
+        tile_size: u32,
+        caption_font: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        if columns == 0 || tile_size < MIN_TILE_SIZE {
+            return Err(Error::TypeMismatch {
+                val: format!("{} columns of {}px tiles", columns, tile_size),
+                expect: format!("at least 1 column of {}px tiles", MIN_TILE_SIZE),
+                found: "invalid sheet layout".to_string(),
+            });
+        }
+        // tiles follow the order mode of the series, and the query of smart series
+        let font = match caption_font {
+            Some(path) => Some(FontVec::try_from_vec(std::fs::read(path)?).map_err(|e| {
+                Error::TypeMismatch {
+                    val: path.to_string(),
+                    expect: "TrueType or OpenType font".to_string(),
+                    found: e.to_string(),
+                }
+            })?),
+            None => None,
+        };
+        let members: Vec<(u64, String, Option<u64>, String)> = {
+            let order = self.get_series_members(series_uuid)?;
+            let db = self.db.get()?;
+            let mut media_stmt =
+                db.prepare("SELECT hash, IFNULL(caption, filename) FROM media WHERE id = ?;")?;
+            let mut no_stmt = db.prepare(
+                "SELECT series_no FROM media_series_ref WHERE series_uuid = ? AND media_id = ?;",
+            )?;
+            order
+                .into_iter()
+                .map(|id| {
+                    let (hash, caption) =
+                        media_stmt.query_row(params![id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                    let no = no_stmt
+                        .query_row(params![series_uuid, id], |row| row.get(0))
+                        .optional()?
+                        .flatten();
+                    Ok((id, hash, no, caption))
+                })
+                .collect::<Result<_>>()?
+        };
+        if members.is_empty() {
+            return Err(Error::NotExists(format!("Media in series {}", series_uuid)));
+        }
+        // captions and the font are signed only when they are drawn
+        let signature = self.hash_algo.do_hash_str(
+            &members
+                .iter()
+                .map(|(_, hash, no, caption)| match caption_font {
+                    Some(_) => format!("{}:{:?}:{}", hash, no, caption),
+                    None => format!("{}:{:?}", hash, no),
+                })
+                .chain(caption_font.map(str::to_string))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        )?;
+
+        let thumbnail_db = self.thumbnail_db.get()?;
+        let cached: Option<(i64, Vec<u8>)> = thumbnail_db
+            .query_row(
+                "SELECT id, image FROM series_sheet WHERE series_uuid = ? AND columns = ?
+                    AND tile_size = ? AND captions = ? AND signature = ?;",
+                params![series_uuid, columns, tile_size, font.is_some(), signature],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if let Some((id, image)) = cached {
+            thumbnail_db.execute(
+                &format!(
+                    "UPDATE series_sheet SET time_access = {} WHERE id = ?;",
+                    SQL_NOW
+                ),
+                params![id],
+            )?;
+            return Ok(image);
+        }
+
+        let spec = ThumbnailSpec::new(tile_size, tile_size, ThumbnailFormat::Png);
+        let requests: Vec<_> = members
+            .iter()
+            .map(|(id, ..)| self.request_thumbnail(*id, &spec, ThumbnailPriority::High))
+            .collect();
+        let caption_height = match font {
+            Some(_) => (tile_size / 6).max(MIN_CAPTION_HEIGHT),
+            None => 0,
+        };
+        let grid_columns = columns.min(members.len() as u32);
+        let rows = (members.len() as u32).div_ceil(grid_columns);
+        let row_height = tile_size + caption_height + SHEET_GAP;
+        let mut sheet = RgbImage::from_pixel(
+            grid_columns * (tile_size + SHEET_GAP) + SHEET_GAP,
+            rows * row_height + SHEET_GAP,
+            SHEET_BACKGROUND,
+        );
+        for (i, request) in requests.into_iter().enumerate() {
+            let x = SHEET_GAP + (i as u32 % grid_columns) * (tile_size + SHEET_GAP);
+            let y = SHEET_GAP + (i as u32 / grid_columns) * row_height;
+            if let Some(font) = &font {
+                let caption = &members[i].3;
+                draw_caption(
+                    &mut sheet,
+                    font,
+                    caption,
+                    x,
+                    y + tile_size,
+                    (tile_size, caption_height),
+                );
+            }
+            for (dx, dy) in (0..tile_size).flat_map(|dx| (0..tile_size).map(move |dy| (dx, dy))) {
+                sheet.put_pixel(x + dx, y + dy, TILE_BACKGROUND);
+            }
+            // media without thumbnail are left blank.
+            if let Ok(thumb) = request.wait() {
+                let thumb = image::load_from_memory(&thumb)?.to_rgb8();
+                let (left, top) = (
+                    (tile_size - thumb.width().min(tile_size)) / 2,
+                    (tile_size - thumb.height().min(tile_size)) / 2,
+                );
+                sheet.copy_from(&thumb, x + left, y + top)?;
+            }
+        }
+
+        let mut image: Vec<u8> = Vec::new();
+        ThumbnailSpec::default().encode(&image::DynamicImage::ImageRgb8(sheet), &mut image)?;
+        thumbnail_db.execute(
+            &format!(
+                "INSERT OR REPLACE INTO series_sheet
+                    (series_uuid, columns, tile_size, captions, signature, image, size, time_access)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, {});",
+                SQL_NOW
+            ),
+            params![
+                series_uuid,
+                columns,
+                tile_size,
+                font.is_some(),
+                signature,
+                image,
+                image.len()
+            ],
+        )?;
+        evict_over_budget(&thumbnail_db)?;
+        Ok(image)
+    }
+
+    pub(crate) fn remove_series_sheets(&self, series_uuid: &Uuid) -> Result<()> {
+        self.thumbnail_db.get()?.execute(
+            "DELETE FROM series_sheet WHERE series_uuid = ?;",
+            params![series_uuid],
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::params;
+
+use super::super::misc::{config, tools, Error, Result, Uuid};
+use super::{Library, TagSearchResult};
+
+pub(crate) const TAG_ALIAS_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tag_alias(
+        normalized TEXT PRIMARY KEY NOT NULL UNIQUE,
+        alias TEXT NOT NULL,
+        tag_uuid CHAR(36) NOT NULL,
+        FOREIGN KEY(tag_uuid) REFERENCES tag(uuid)
+    );
+    ";
+
+// How the candidate matches the query, less is better.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum MatchKind {
+    Prefix,
+    Contains,
+    Fuzzy,
+}
+
+// Shorter queries would be within the distance of almost everything.
+const FUZZY_MIN_LEN: usize = 3;
+
+// Candidates are compared with the query and also their prefixes in the same length,
+// so that typos are tolerated while typing.
+fn match_caption(query: &str, candidate: &str) -> Option<(MatchKind, usize)> {
+    if candidate.starts_with(query) {
+        return Some((MatchKind::Prefix, 0));
+    }
+    if candidate.contains(query) {
+        return Some((MatchKind::Contains, 0));
+    }
+    let query_len = query.chars().count();
+    if query_len < FUZZY_MIN_LEN {
+        return None;
+    }
+    let prefix: String = candidate.chars().take(query_len).collect();
+    let distance = strsim::levenshtein(query, candidate).min(strsim::levenshtein(query, &prefix));
+    if distance <= query_len / 4 + 1 {
+        Some((MatchKind::Fuzzy, distance))
+    } else {
+        None
+    }
+}
+
+// (uuid, alias, normalized, caption, media_count), alias is None for the caption itself.
+type Candidate = (Uuid, Option<String>, String, String, usize);
+
+const SEARCH_CANDIDATES: &str = "(
+    SELECT uuid, NULL AS alias, normalized, caption, media_count FROM tag
+    UNION ALL SELECT tag_uuid, alias, tag_alias.normalized, caption, media_count
+        FROM tag_alias JOIN tag ON tag.uuid = tag_alias.tag_uuid
+    )";
+
+impl Library {
+    // Another caption of the tag, which is matched by get_tag_by_caption and search_tags.
+    pub fn add_tag_alias(&mut self, tag_uuid: &Uuid, alias: &str) -> Result<()> {
+        self.tag_exist_guard(tag_uuid)?;
+        let normalized = tools::normalize_caption(alias);
+        if normalized.is_empty() {
+            return Err(Error::NotMatch(format!("Alias \"{}\"", alias)));
+        }
+        match self.get_tag_by_caption(alias) {
+            Ok(uuid) if &uuid == tag_uuid => {}
+            Ok(uuid) => {
+                return Err(Error::AlreadyExists(format!(
+                    "Caption or alias \"{}\" of tag {}",
+                    alias, uuid
+                )))
+            }
+            Err(Error::NotExists(_)) => {}
+            Err(e) => return Err(e),
+        }
+        self.db.get()?.execute(
+            "INSERT OR REPLACE INTO tag_alias (normalized, alias, tag_uuid) VALUES (?, ?, ?);",
+            params![normalized, alias.trim(), tag_uuid],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_tag_alias(&mut self, alias: &str) -> Result<()> {
+        self.db.get()?.execute(
+            "DELETE FROM tag_alias WHERE normalized = ?;",
+            params![tools::normalize_caption(alias)],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_tag_aliases(&self, tag_uuid: &Uuid) -> Result<Vec<String>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare("SELECT alias FROM tag_alias WHERE tag_uuid = ? ORDER BY alias;")?
+            .query_map(params![tag_uuid], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Tags whose caption or alias starts with, contains, or nearly matches the query, for autocomplete.
+    // Better matches go first, then the more used ones. Only the most used tags are matched fuzzily.
+    pub fn search_tags(&self, query: &str, limit: usize) -> Result<Vec<TagSearchResult>> {
+        let query = tools::normalize_caption(query);
+        let pattern = format!(
+            "%{}%",
+            query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+        let db = self.db.get()?;
+        let read_candidate = |row: &rusqlite::Row| -> rusqlite::Result<Candidate> {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        };
+        let mut candidates: Vec<Candidate> = db
+            .prepare(&format!(
+                "SELECT * FROM {} WHERE normalized LIKE ? ESCAPE '\\';",
+                SEARCH_CANDIDATES
+            ))?
+            .query_map(params![pattern], read_candidate)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let found: HashSet<Uuid> = candidates.iter().map(|c| c.0).collect();
+        // fuzzy matches go after others, not needed if there are enough
+        if found.len() < limit && query.chars().count() >= FUZZY_MIN_LEN {
+            candidates.extend(
+                db.prepare(&format!(
+                    "SELECT * FROM {} WHERE normalized NOT LIKE ? ESCAPE '\\'
+                        ORDER BY media_count DESC LIMIT ?;",
+                    SEARCH_CANDIDATES
+                ))?
+                .query_map(
+                    params![pattern, config::FUZZY_TAG_CANDIDATES],
+                    read_candidate,
+                )?
+                .collect::<rusqlite::Result<Vec<_>>>()?,
+            );
+        }
+
+        let mut best: HashMap<Uuid, ((MatchKind, usize), TagSearchResult)> = HashMap::new();
+        for (uuid, alias, normalized, caption, media_count) in candidates {
+            let matched = match match_caption(&query, &normalized) {
+                Some(matched) => matched,
+                None => continue,
+            };
+            match best.get(&uuid) {
+                Some((m, _)) if *m <= matched => {}
+                _ => {
+                    let result = TagSearchResult {
+                        uuid,
+                        caption,
+                        alias,
+                        media_count,
+                        distance: matched.1,
+                    };
+                    best.insert(uuid, (matched, result));
+                }
+            }
+        }
+        let mut results: Vec<((MatchKind, usize), TagSearchResult)> = best.into_values().collect();
+        results.sort_by_key(|(matched, r)| (*matched, Reverse(r.media_count), r.caption.clone()));
+        Ok(results.into_iter().take(limit).map(|(_, r)| r).collect())
+    }
+}
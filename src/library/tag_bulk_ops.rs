@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+
+use rusqlite::params;
+
+use super::super::misc::{Result, Uuid};
+use super::detail_ops::sync_detail_tags;
+use super::{Library, MediaQuery, TagChanges};
+
+// Change of media_count of each tag, written once at the end of the transaction.
+type CountDelta = HashMap<Uuid, i64>;
+
+fn update_media_count(db: &rusqlite::Connection, delta: CountDelta) -> Result<()> {
+    let mut stmt = db.prepare("UPDATE tag SET media_count = media_count + ? WHERE uuid = ?;")?;
+    for (tag_uuid, delta) in delta {
+        if delta != 0 {
+            stmt.execute(params![delta, tag_uuid])?;
+        }
+    }
+    Ok(())
+}
+
+// Media not existing are recorded as missing rather than failing the whole operation.
+fn existing_media(
+    db: &rusqlite::Connection,
+    ids: &[u64],
+    changes: &mut TagChanges,
+) -> Result<Vec<u64>> {
+    let mut stmt = db.prepare("SELECT EXISTS(SELECT 1 FROM media WHERE id = ?);")?;
+    let mut existing = vec![];
+    let mut seen = HashSet::new();
+    for &id in ids {
+        if !seen.insert(id) {
+            continue;
+        }
+        if stmt.query_row(params![id], |row| row.get(0))? {
+            existing.push(id);
+        } else {
+            changes.missing.push(id);
+        }
+    }
+    Ok(existing)
+}
+
+impl Library {
+    // Tags with their implied ones, all tags must exist.
+    fn expand_tags(&self, tags: &[Uuid]) -> Result<Vec<Uuid>> {
+        let mut expanded = vec![];
+        for tag_uuid in tags {
+            self.tag_exist_guard(tag_uuid)?;
+            for tag_uuid in std::iter::once(*tag_uuid).chain(self.get_implied_tags(tag_uuid)?) {
+                if !expanded.contains(&tag_uuid) {
+                    expanded.push(tag_uuid);
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    // Tag all media with all tags and their implied ones in one transaction.
+    // Tags already on media are skipped.
+    pub fn add_tags_bulk(&mut self, ids: &[u64], tags: &[Uuid]) -> Result<TagChanges> {
+        let tags = self.expand_tags(tags)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let mut changes = TagChanges::default();
+        let mut delta = CountDelta::new();
+        let mut changed_ids = vec![];
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR IGNORE INTO media_tag_ref (media_id, tag_uuid) VALUES (?, ?);",
+            )?;
+            for id in existing_media(&tx, ids, &mut changes)? {
+                let mut changed = false;
+                for tag_uuid in &tags {
+                    if insert.execute(params![id, tag_uuid])? > 0 {
+                        *delta.entry(*tag_uuid).or_default() += 1;
+                        changes.added += 1;
+                        changed = true;
+                    }
+                }
+                if changed {
+                    changes.media += 1;
+                    changed_ids.push(id);
+                }
+            }
+        }
+        update_media_count(&tx, delta)?;
+        sync_detail_tags(&tx, &changed_ids)?;
+        tx.commit()?;
+        Ok(changes)
+    }
+
+    // Implied tags are kept, since they may be added on their own.
+    pub fn remove_tags_bulk(&mut self, ids: &[u64], tags: &[Uuid]) -> Result<TagChanges> {
+        for tag_uuid in tags {
+            self.tag_exist_guard(tag_uuid)?;
+        }
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let mut changes = TagChanges::default();
+        let mut delta = CountDelta::new();
+        let mut changed_ids = vec![];
+        {
+            let mut delete =
+                tx.prepare("DELETE FROM media_tag_ref WHERE media_id = ? AND tag_uuid = ?;")?;
+            for id in existing_media(&tx, ids, &mut changes)? {
+                let mut changed = false;
+                for tag_uuid in tags {
+                    if delete.execute(params![id, tag_uuid])? > 0 {
+                        *delta.entry(*tag_uuid).or_default() -= 1;
+                        changes.removed += 1;
+                        changed = true;
+                    }
+                }
+                if changed {
+                    changes.media += 1;
+                    changed_ids.push(id);
+                }
+            }
+        }
+        update_media_count(&tx, delta)?;
+        sync_detail_tags(&tx, &changed_ids)?;
+        tx.commit()?;
+        Ok(changes)
+    }
+
+    // Media end up with exactly the tags and their implied ones.
+    pub fn replace_tags(&mut self, ids: &[u64], tags: &[Uuid]) -> Result<TagChanges> {
+        let tags = self.expand_tags(tags)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let mut changes = TagChanges::default();
+        let mut delta = CountDelta::new();
+        let mut changed_ids = vec![];
+        {
+            let mut select =
+                tx.prepare("SELECT tag_uuid FROM media_tag_ref WHERE media_id = ?;")?;
+            let mut insert =
+                tx.prepare("INSERT INTO media_tag_ref (media_id, tag_uuid) VALUES (?, ?);")?;
+            let mut delete =
+                tx.prepare("DELETE FROM media_tag_ref WHERE media_id = ? AND tag_uuid = ?;")?;
+            for id in existing_media(&tx, ids, &mut changes)? {
+                let current: Vec<Uuid> = select
+                    .query_map(params![id], |row| row.get(0))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                let mut changed = false;
+                for tag_uuid in current.iter().filter(|t| !tags.contains(t)) {
+                    delete.execute(params![id, tag_uuid])?;
+                    *delta.entry(*tag_uuid).or_default() -= 1;
+                    changes.removed += 1;
+                    changed = true;
+                }
+                for tag_uuid in tags.iter().filter(|t| !current.contains(t)) {
+                    insert.execute(params![id, tag_uuid])?;
+                    *delta.entry(*tag_uuid).or_default() += 1;
+                    changes.added += 1;
+                    changed = true;
+                }
+                if changed {
+                    changes.media += 1;
+                    changed_ids.push(id);
+                }
+            }
+        }
+        update_media_count(&tx, delta)?;
+        sync_detail_tags(&tx, &changed_ids)?;
+        tx.commit()?;
+        Ok(changes)
+    }
+
+    // Tag media matched by the query.
+    pub fn add_tags_to_query(&mut self, query: &MediaQuery, tags: &[Uuid]) -> Result<TagChanges> {
+        let ids = self.query_media_by(query)?;
+        self.add_tags_bulk(&ids, tags)
+    }
+
+    pub fn remove_tags_from_query(
+        &mut self,
+        query: &MediaQuery,
+        tags: &[Uuid],
+    ) -> Result<TagChanges> {
+        let ids = self.query_media_by(query)?;
+        self.remove_tags_bulk(&ids, tags)
+    }
+}
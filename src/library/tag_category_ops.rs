@@ -0,0 +1,218 @@
+use rusqlite::params;
+
+use super::super::misc::{tools, Error, Result, Uuid};
+use super::{Library, SQLite, Tag, TagCategory};
+
+// Tags without explicit category fall into it, and it could not be deleted.
+pub(crate) const DEFAULT_TAG_CATEGORY: &str = "general";
+
+const TAG_CATEGORY_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tag_category(
+        name TEXT PRIMARY KEY NOT NULL UNIQUE,
+        color CHAR(7) NOT NULL, /* #RRGGBB */
+        sort_order INTEGER NOT NULL
+    );
+    ";
+
+const DEFAULT_TAG_CATEGORIES: &str = "
+    INSERT OR IGNORE INTO tag_category (name, color, sort_order) VALUES
+        ('artist', '#AA0000', 0),
+        ('character', '#00AA00', 1),
+        ('copyright', '#AA00AA', 2),
+        ('general', '#0073FF', 3),
+        ('meta', '#FF8800', 4);
+    ";
+
+const TAG_SELECT: &str = "
+    SELECT tag.uuid, tag.caption, tag.comment, tag.media_count,
+           tag_category.name, tag_category.color, tag_category.sort_order
+    FROM tag JOIN tag_category ON tag.category = tag_category.name";
+
+// Default categories are only added once, so that deleted ones would not come back.
+pub(crate) fn migrate_tag_category(db: &SQLite) -> Result<()> {
+    let db = db.get()?;
+    if !tools::has_column(&db, "tag_category", "name")? {
+        db.execute_batch(TAG_CATEGORY_SCHEMA)?;
+        db.execute_batch(DEFAULT_TAG_CATEGORIES)?;
+    }
+    if !tools::has_column(&db, "tag", "category")? {
+        db.execute(
+            "ALTER TABLE tag ADD COLUMN category TEXT NOT NULL DEFAULT 'general';",
+            params![],
+        )?;
+    }
+    Ok(())
+}
+
+fn check_color(color: &str) -> Result<()> {
+    let is_hex = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex {
+        Ok(())
+    } else {
+        Err(Error::TypeMismatch {
+            val: color.to_string(),
+            expect: "color like #RRGGBB".to_string(),
+            found: "other string".to_string(),
+        })
+    }
+}
+
+fn row_to_tag(row: &rusqlite::Row) -> rusqlite::Result<Tag> {
+    Ok(Tag {
+        uuid: row.get(0)?,
+        caption: row.get(1)?,
+        comment: row.get(2)?,
+        media_count: row.get(3)?,
+        category: TagCategory {
+            name: row.get(4)?,
+            color: row.get(5)?,
+            sort_order: row.get(6)?,
+        },
+    })
+}
+
+impl Library {
+    pub fn create_tag_category(&mut self, name: &str, color: &str, sort_order: i64) -> Result<()> {
+        check_color(color)?;
+        let name = name.trim().to_lowercase();
+        if name.is_empty() || name.contains(':') {
+            return Err(Error::NotMatch(format!("Tag category name \"{}\"", name)));
+        }
+        if self.get_tag_category(&name).is_ok() {
+            return Err(Error::AlreadyExists(format!("Tag category {}", name)));
+        }
+        self.db.get()?.execute(
+            "INSERT INTO tag_category (name, color, sort_order) VALUES (?, ?, ?);",
+            params![name, color.to_uppercase(), sort_order],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_tag_category(&mut self, name: &str, color: &str, sort_order: i64) -> Result<()> {
+        check_color(color)?;
+        let updated = self.db.get()?.execute(
+            "UPDATE tag_category SET color = ?, sort_order = ? WHERE name = ?;",
+            params![color.to_uppercase(), sort_order, name],
+        )?;
+        if updated == 0 {
+            return Err(Error::NotExists(format!("Tag category {}", name)));
+        }
+        Ok(())
+    }
+
+    // Tags in the category are moved to the default one.
+    pub fn delete_tag_category(&mut self, name: &str) -> Result<()> {
+        if name == DEFAULT_TAG_CATEGORY {
+            return Err(Error::Occupied(format!("Tag category {}", name)));
+        }
+        self.get_tag_category(name)?;
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        tx.execute(
+            "UPDATE tag SET category = ? WHERE category = ?;",
+            params![DEFAULT_TAG_CATEGORY, name],
+        )?;
+        tx.execute("DELETE FROM tag_category WHERE name = ?;", params![name])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn get_tag_category(&self, name: &str) -> Result<TagCategory> {
+        self.db
+            .get()?
+            .query_row(
+                "SELECT name, color, sort_order FROM tag_category WHERE name = ?;",
+                params![name],
+                |row| {
+                    Ok(TagCategory {
+                        name: row.get(0)?,
+                        color: row.get(1)?,
+                        sort_order: row.get(2)?,
+                    })
+                },
+            )
+            .map_err(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => {
+                    Error::NotExists(format!("Tag category {}", name))
+                }
+                _ => Error::DB(e),
+            })
+    }
+
+    pub fn get_tag_categories(&self) -> Result<Vec<TagCategory>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare("SELECT name, color, sort_order FROM tag_category ORDER BY sort_order, name;")?
+            .query_map(params![], |row| {
+                Ok(TagCategory {
+                    name: row.get(0)?,
+                    color: row.get(1)?,
+                    sort_order: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn set_tag_category(&mut self, tag_uuid: &Uuid, category: &str) -> Result<()> {
+        self.tag_exist_guard(tag_uuid)?;
+        self.get_tag_category(category)?;
+        self.db.get()?.execute(
+            "UPDATE tag SET category = ? WHERE uuid = ?;",
+            params![category, tag_uuid],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_tags_by_category(&self, category: &str) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare("SELECT uuid FROM tag WHERE category = ? ORDER BY caption;")?
+            .query_map(params![category], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn get_tag(&self, tag_uuid: &Uuid) -> Result<Tag> {
+        self.tag_exist_guard(tag_uuid)?;
+        Ok(self.db.get()?.query_row(
+            &format!("{} WHERE tag.uuid = ?;", TAG_SELECT),
+            params![tag_uuid],
+            row_to_tag,
+        )?)
+    }
+
+    // Tags of the media with their categories, in the order of categories for grouping.
+    pub fn get_media_tags(&self, id: u64) -> Result<Vec<Tag>> {
+        self.media_exist_guard(id)?;
+        Ok(self
+            .db
+            .get()?
+            .prepare(&format!(
+                "{} JOIN media_tag_ref ON media_tag_ref.tag_uuid = tag.uuid
+                    WHERE media_tag_ref.media_id = ?
+                    ORDER BY tag_category.sort_order, tag_category.name, tag.caption;",
+                TAG_SELECT
+            ))?
+            .query_map(params![id], row_to_tag)?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // "artist:foo" goes to category "artist" and caption "foo", None if the prefix is not a category.
+    pub(crate) fn split_tag_namespace<'a>(
+        &self,
+        caption: &'a str,
+    ) -> Result<Option<(String, &'a str)>> {
+        let (namespace, caption) = match caption.split_once(':') {
+            Some(split) => split,
+            None => return Ok(None),
+        };
+        match self.get_tag_category(&namespace.trim().to_lowercase()) {
+            Ok(category) => Ok(Some((category.name, caption.trim()))),
+            Err(Error::NotExists(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use rusqlite::params;
+
+use super::super::misc::{tools, Error, Result, Uuid};
+use super::detail_ops::sync_detail_tags;
+use super::series_meta_ops::repoint_query_tag;
+use super::{Library, TagChanges};
+
+// Point references of tag ?1 to tag ?2, rows which would be duplicated are left and deleted after.
+const REPOINT_TAG: [&str; 5] = [
+    "UPDATE OR IGNORE tag_parent_ref SET tag_uuid = ?2 WHERE tag_uuid = ?1;",
+    "UPDATE OR IGNORE tag_parent_ref SET parent_uuid = ?2 WHERE parent_uuid = ?1;",
+    "UPDATE OR IGNORE tag_implication SET tag_uuid = ?2 WHERE tag_uuid = ?1;",
+    "UPDATE OR IGNORE tag_implication SET implied_uuid = ?2 WHERE implied_uuid = ?1;",
+    "UPDATE tag_alias SET tag_uuid = ?2 WHERE tag_uuid = ?1;",
+];
+
+const REPOINT_TAG_LEFT: [&str; 2] = [
+    "DELETE FROM tag_parent_ref WHERE ?1 IN (tag_uuid, parent_uuid) OR tag_uuid = parent_uuid;",
+    "DELETE FROM tag_implication WHERE ?1 IN (tag_uuid, implied_uuid) OR tag_uuid = implied_uuid;",
+];
+
+// Whether tag ?1 reaches itself through parents or implications, all rows of a new cycle
+// go through the tag merged into.
+const TAG_CYCLE: [(&str, &str); 2] = [
+    (
+        "parent",
+        "WITH RECURSIVE up(uuid) AS (
+            SELECT parent_uuid FROM tag_parent_ref WHERE tag_uuid = ?1
+            UNION SELECT parent_uuid FROM tag_parent_ref JOIN up ON tag_uuid = up.uuid
+        ) SELECT EXISTS(SELECT 1 FROM up WHERE uuid = ?1);",
+    ),
+    (
+        "implication",
+        "WITH RECURSIVE implied(uuid) AS (
+            SELECT implied_uuid FROM tag_implication WHERE tag_uuid = ?1
+            UNION SELECT implied_uuid FROM tag_implication JOIN implied ON tag_uuid = implied.uuid
+        ) SELECT EXISTS(SELECT 1 FROM implied WHERE uuid = ?1);",
+    ),
+];
+
+fn recount_tag(db: &rusqlite::Connection, tag_uuid: &Uuid) -> Result<()> {
+    db.execute(
+        "UPDATE tag SET media_count = (SELECT COUNT(*) FROM media_tag_ref WHERE tag_uuid = ?1)
+            WHERE uuid = ?1;",
+        params![tag_uuid],
+    )?;
+    Ok(())
+}
+
+impl Library {
+    // "artist:foo" moves the tag to category "artist" as well.
+    pub fn rename_tag(&mut self, tag_uuid: &Uuid, caption: &str) -> Result<()> {
+        self.tag_exist_guard(tag_uuid)?;
+        let (category, caption) = match self.split_tag_namespace(caption)? {
+            Some((category, name)) => (Some(category), name),
+            None => (None, caption.trim()),
+        };
+        if tools::normalize_caption(caption).is_empty() {
+            return Err(Error::NotMatch(format!("Tag caption \"{}\"", caption)));
+        }
+        match self.get_tag_by_caption(caption) {
+            Ok(uuid) if &uuid != tag_uuid => {
+                return Err(Error::AlreadyExists(format!("Tag {}", caption)))
+            }
+            Ok(_) | Err(Error::NotExists(_)) => {}
+            Err(e) => return Err(e),
+        }
+        self.db.get()?.execute(
+            "UPDATE tag SET caption = ?, normalized = ?, category = IFNULL(?, category)
+                WHERE uuid = ?;",
+            params![
+                caption,
+                tools::normalize_caption(caption),
+                category,
+                tag_uuid
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Media, aliases, parents and implications of tags in from go to into, and tags in from are deleted.
+    // Captions of merged tags become aliases of into, so lookup of them still works.
+    // Fails with Cycle if into would be its own ancestor or implication after merging.
+    pub fn merge_tags(&mut self, into: &Uuid, from: &[Uuid]) -> Result<TagChanges> {
+        self.tag_exist_guard(into)?;
+        let mut merged: Vec<(Uuid, String)> = vec![];
+        for tag_uuid in from.iter().filter(|t| *t != into) {
+            if !merged.iter().any(|(t, _)| t == tag_uuid) {
+                merged.push((*tag_uuid, self.get_tag(tag_uuid)?.caption));
+            }
+        }
+        let mut ids = vec![];
+        for (tag_uuid, _) in &merged {
+            ids.extend(self.query_media_by_tag(tag_uuid, false)?);
+        }
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let mut changes = TagChanges {
+            media: ids.len(),
+            ..Default::default()
+        };
+        for (tag_uuid, caption) in &merged {
+            changes.added += tx.execute(
+                "INSERT OR IGNORE INTO media_tag_ref (media_id, tag_uuid)
+                    SELECT media_id, ?2 FROM media_tag_ref WHERE tag_uuid = ?1;",
+                params![tag_uuid, into],
+            )?;
+            changes.removed += tx.execute(
+                "DELETE FROM media_tag_ref WHERE tag_uuid = ?;",
+                params![tag_uuid],
+            )?;
+            for stmt in REPOINT_TAG {
+                tx.execute(stmt, params![tag_uuid, into])?;
+            }
+            for stmt in REPOINT_TAG_LEFT {
+                tx.execute(stmt, params![tag_uuid])?;
+            }
+            repoint_query_tag(&tx, tag_uuid, Some(into))?;
+            tx.execute(
+                "INSERT OR IGNORE INTO tag_alias (normalized, alias, tag_uuid) VALUES (?, ?, ?);",
+                params![tools::normalize_caption(caption), caption, into],
+            )?;
+            tx.execute("DELETE FROM tag WHERE uuid = ?;", params![tag_uuid])?;
+        }
+        for (relation, stmt) in TAG_CYCLE {
+            if tx.query_row(stmt, params![into], |row| row.get(0))? {
+                return Err(Error::Cycle(format!(
+                    "Tag {} in its own {} after merging",
+                    into, relation
+                )));
+            }
+        }
+        recount_tag(&tx, into)?;
+        sync_detail_tags(&tx, &ids)?;
+        tx.commit()?;
+        self.summary.tag_count -= merged.len();
+        Ok(changes)
+    }
+
+    // Move the media from the tag to a new tag in the same category, returns the new one.
+    // Every media in ids must carry the tag.
+    pub fn split_tag(&mut self, tag_uuid: &Uuid, ids: &[u64], caption: String) -> Result<Uuid> {
+        let tag = self.get_tag(tag_uuid)?;
+        let (category, caption) = match self.split_tag_namespace(&caption)? {
+            Some((category, name)) => (category, name.to_string()),
+            None => (tag.category.name, caption.trim().to_string()),
+        };
+        // captions are unique across categories
+        if self.get_tag_by_caption(&caption).is_ok() {
+            return Err(Error::AlreadyExists(format!("Tag {}", caption)));
+        }
+        if tools::normalize_caption(&caption).is_empty() {
+            return Err(Error::NotMatch(format!("Tag caption \"{}\"", caption)));
+        }
+        let mut ids = ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let new_uuid = Uuid::new_v4();
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        tx.execute(
+            "INSERT INTO tag (uuid, caption, media_count, comment, normalized, category)
+                VALUES (?, ?, 0, ?, ?, ?);",
+            params![
+                new_uuid,
+                caption,
+                tag.comment,
+                tools::normalize_caption(&caption),
+                category
+            ],
+        )?;
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE media_tag_ref SET tag_uuid = ?3 WHERE media_id = ?1 AND tag_uuid = ?2;",
+            )?;
+            for id in &ids {
+                if stmt.execute(params![id, tag_uuid, new_uuid])? == 0 {
+                    return Err(Error::NotIn {
+                        a: format!("Media {}", id),
+                        b: format!("tag {}", tag_uuid),
+                    });
+                }
+            }
+        }
+        recount_tag(&tx, tag_uuid)?;
+        recount_tag(&tx, &new_uuid)?;
+        sync_detail_tags(&tx, &ids)?;
+        tx.commit()?;
+        self.summary.tag_count += 1;
+        Ok(new_uuid)
+    }
+}
@@ -0,0 +1,146 @@
+use rusqlite::params;
+
+use super::super::misc::{Error, Result, Uuid};
+use super::detail_ops::sync_all_detail_tags;
+use super::Library;
+
+// Tagging media with tag_uuid also tags it with implied_uuid, e.g. "persian" implies "cat".
+pub(crate) const TAG_IMPLICATION_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tag_implication(
+        id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+        tag_uuid CHAR(36) NOT NULL,
+        implied_uuid CHAR(36) NOT NULL,
+        FOREIGN KEY(tag_uuid) REFERENCES tag(uuid),
+        FOREIGN KEY(implied_uuid) REFERENCES tag(uuid),
+        CONSTRAINT unique_tag_implication UNIQUE (tag_uuid, implied_uuid)
+    );
+    ";
+
+const TAG_IMPLIED_CTE: &str = "
+    WITH RECURSIVE implied(uuid) AS (
+        SELECT ?1
+        UNION
+        SELECT tag_implication.implied_uuid FROM tag_implication
+            JOIN implied ON tag_implication.tag_uuid = implied.uuid
+    )";
+
+// Returns whether the media is newly tagged.
+pub(crate) fn insert_tag_ref(db: &rusqlite::Connection, id: u64, tag_uuid: &Uuid) -> Result<bool> {
+    let inserted = db.execute(
+        "INSERT OR IGNORE INTO media_tag_ref (media_id, tag_uuid) VALUES (?, ?);",
+        params![id, tag_uuid],
+    )? > 0;
+    if inserted {
+        db.execute(
+            "UPDATE tag SET media_count = media_count + 1 WHERE uuid = ?;",
+            params![tag_uuid],
+        )?;
+    }
+    Ok(inserted)
+}
+
+impl Library {
+    pub fn add_tag_implication(&mut self, tag_uuid: &Uuid, implied_uuid: &Uuid) -> Result<()> {
+        self.tag_exist_guard(tag_uuid)?;
+        self.tag_exist_guard(implied_uuid)?;
+        if tag_uuid == implied_uuid || self.get_implied_tags(implied_uuid)?.contains(tag_uuid) {
+            return Err(Error::Cycle(format!(
+                "Tag {} implied by its implication {}",
+                tag_uuid, implied_uuid
+            )));
+        }
+        self.db.get()?.execute(
+            "INSERT OR IGNORE INTO tag_implication (tag_uuid, implied_uuid) VALUES (?, ?);",
+            params![tag_uuid, implied_uuid],
+        )?;
+        Ok(())
+    }
+
+    // Media already tagged keep the implied tags.
+    pub fn remove_tag_implication(&mut self, tag_uuid: &Uuid, implied_uuid: &Uuid) -> Result<()> {
+        self.db.get()?.execute(
+            "DELETE FROM tag_implication WHERE tag_uuid = ? AND implied_uuid = ?;",
+            params![tag_uuid, implied_uuid],
+        )?;
+        Ok(())
+    }
+
+    // Tags directly implied by the tag.
+    pub fn get_tag_implications(&self, tag_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare("SELECT implied_uuid FROM tag_implication WHERE tag_uuid = ?;")?
+            .query_map(params![tag_uuid], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Tags implied by the tag transitively, excluding itself.
+    pub fn get_implied_tags(&self, tag_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(&format!(
+                "{} SELECT uuid FROM implied WHERE uuid != ?1;",
+                TAG_IMPLIED_CTE
+            ))?
+            .query_map(params![tag_uuid], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Dry run of add_tag, tags which would be newly added to the media.
+    pub fn preview_add_tag(&self, id: u64, tag_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        self.tag_exist_guard(tag_uuid)?;
+        self.media_exist_guard(id)?;
+        Ok(self
+            .db
+            .get()?
+            .prepare(&format!(
+                "{} SELECT uuid FROM implied WHERE uuid NOT IN
+                    (SELECT tag_uuid FROM media_tag_ref WHERE media_id = ?2);",
+                TAG_IMPLIED_CTE
+            ))?
+            .query_map(params![tag_uuid, id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Add implied tags to media tagged before the rules, returns count of tags added.
+    // Nothing is written for dry run.
+    pub fn apply_implications(&mut self, dry_run: bool) -> Result<usize> {
+        let tags: Vec<Uuid> = self
+            .db
+            .get()?
+            .prepare("SELECT DISTINCT tag_uuid FROM tag_implication;")?
+            .query_map(params![], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut rules = vec![];
+        for tag_uuid in tags {
+            let implied = self.get_implied_tags(&tag_uuid)?;
+            rules.push((tag_uuid, implied));
+        }
+
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        let mut added = 0;
+        for (tag_uuid, implied) in rules {
+            for implied_uuid in implied {
+                added += tx.execute(
+                    "INSERT OR IGNORE INTO media_tag_ref (media_id, tag_uuid)
+                        SELECT media_id, ?2 FROM media_tag_ref WHERE tag_uuid = ?1;",
+                    params![tag_uuid, implied_uuid],
+                )?;
+            }
+        }
+        tx.execute(
+            "UPDATE tag SET media_count =
+                (SELECT COUNT(*) FROM media_tag_ref WHERE media_tag_ref.tag_uuid = tag.uuid)
+             WHERE uuid IN (SELECT implied_uuid FROM tag_implication);",
+            params![],
+        )?;
+        sync_all_detail_tags(&tx)?;
+        if !dry_run {
+            tx.commit()?;
+        }
+        Ok(added)
+    }
+}
@@ -1,64 +1,90 @@
 use rusqlite::params;
 
-use super::super::misc::{Error, Result, Uuid};
+use super::super::misc::{tools, Error, Result, Uuid};
+use super::detail_ops::sync_detail_tags;
+use super::series_meta_ops::repoint_query_tag;
+use super::tag_category_ops::DEFAULT_TAG_CATEGORY;
+use super::tag_implication_ops::insert_tag_ref;
 use super::Library;
 
 impl Library {
+    // Tags implied by the tag are added too, see preview_add_tag.
     pub fn add_tag(&mut self, id: u64, tag_uuid: &Uuid) -> Result<()> {
-        let db = self.db.get()?;
         self.tag_exist_guard(tag_uuid)?;
         self.media_exist_guard(id)?;
+        let implied = self.get_implied_tags(tag_uuid)?;
 
-        db.execute(
+        let mut db = self.db.get()?;
+        let tx = db.transaction()?;
+        tx.execute(
             "INSERT INTO media_tag_ref (media_id, tag_uuid) VALUES (?, ?);",
             params![id, tag_uuid],
         )?;
-        db.execute(
+        tx.execute(
             "UPDATE tag SET media_count = media_count + 1 WHERE uuid = ?;",
             params![tag_uuid],
         )?;
+        for implied_uuid in implied {
+            insert_tag_ref(&tx, id, &implied_uuid)?;
+        }
+        sync_detail_tags(&tx, &[id])?;
+        tx.commit()?;
         Ok(())
     }
 
     pub fn remove_tag(&mut self, id: u64, tag_uuid: &Uuid) -> Result<()> {
-        let db = self.db.get()?;
         self.tag_exist_guard(tag_uuid)?;
         self.media_exist_guard(id)?;
-
-        let is_media_has_tag: bool = db.query_row(
-            "SELECT EXISTS(SELECT 1 FROM media_tag_ref WHERE media_id = ? AND tag_uuid = ?);",
-            params![id, tag_uuid],
-            |row| Ok(row.get(0)?),
-        )?;
-        if !is_media_has_tag {
-            return Ok(());
-        }
-
-        db.execute(
-            "DELECT FROM media_tag_ref WHERE media_id = ?, AND tag_uuid = ?;",
-            params![id, tag_uuid],
-        )?;
-        db.execute(
-            "UPDATE tag SET media_count = media_count - 1 WHERE uuid =?;",
-            params![tag_uuid],
-        )?;
-
+        self.remove_tags_bulk(&[id], &[*tag_uuid])?;
         Ok(())
     }
 
     // return uuid if tag is already existed
+    // "artist:foo" creates tag "foo" in category "artist".
+    // Captions only matching an alias or the normalized caption of another tag are taken.
     pub fn create_tag(&mut self, caption: String, comment: Option<String>) -> Result<Uuid> {
         match self.get_tag_by_caption(&caption) {
-            Ok(uuid) => return Ok(uuid), // exists
+            Ok(uuid) => {
+                let name = match self.split_tag_namespace(&caption)? {
+                    Some((_, name)) => name,
+                    None => &caption,
+                };
+                if self.get_tag(&uuid)?.caption == name {
+                    return Ok(uuid); // exists
+                }
+                return Err(Error::AlreadyExists(format!(
+                    "Caption or alias \"{}\" of tag {}",
+                    caption, uuid
+                )));
+            }
             Err(Error::NotExists(_)) => (),
             Err(e) => return Err(e),
         };
+        let (category, caption) = match self.split_tag_namespace(&caption)? {
+            Some((category, name)) => {
+                if self.find_tag(name, None).is_ok() {
+                    return Err(Error::AlreadyExists(format!(
+                        "Tag {} in other category",
+                        name
+                    )));
+                }
+                (category, name.to_string())
+            }
+            None => (DEFAULT_TAG_CATEGORY.to_string(), caption),
+        };
 
         let db = self.db.get()?;
         let uuid = Uuid::new_v4();
         db.execute(
-            "INSERT INTO tag (uuid, caption, media_count, comment) VALUES (?, ?, 0, ?);",
-            params![&uuid, caption, comment],
+            "INSERT INTO tag (uuid, caption, media_count, comment, normalized, category)
+                VALUES (?, ?, 0, ?, ?, ?);",
+            params![
+                &uuid,
+                caption,
+                comment,
+                tools::normalize_caption(&caption),
+                category
+            ],
         )?;
         self.summary.tag_count += 1;
         Ok(uuid)
@@ -67,24 +93,60 @@ impl Library {
     pub fn delete_tag(&mut self, tag_uuid: Uuid) -> Result<()> {
         self.tag_exist_guard(&tag_uuid)?;
 
-        let db = self.db.get()?;
+        let ids = self.query_media_by_tag(&tag_uuid, false)?;
+        let mut db = self.db.get()?;
+        let db = db.transaction()?;
         db.execute(
             "DELETE FROM media_tag_ref WHERE tag_uuid = ?;",
             params![tag_uuid],
         )?;
+        sync_detail_tags(&db, &ids)?;
+        // children are left under their other parents, or become root tags.
+        db.execute(
+            "DELETE FROM tag_parent_ref WHERE tag_uuid = ?1 OR parent_uuid = ?1;",
+            params![tag_uuid],
+        )?;
+        db.execute(
+            "DELETE FROM tag_implication WHERE tag_uuid = ?1 OR implied_uuid = ?1;",
+            params![tag_uuid],
+        )?;
+        db.execute(
+            "DELETE FROM tag_alias WHERE tag_uuid = ?;",
+            params![tag_uuid],
+        )?;
+        // stored queries just drop the tag
+        repoint_query_tag(&db, &tag_uuid, None)?;
         db.execute("DELETE FROM tag WHERE uuid = ?;", params![tag_uuid])?;
+        db.commit()?;
         self.summary.tag_count -= 1;
         Ok(())
     }
 
-    // TODO: impl search tags via caption using fuzzy matching
+    // Exact caption goes first, then captions and aliases in normalized form.
+    // Caption like "artist:foo" is looked up in the category if no tag is captioned so.
+    // See search_tags for fuzzy matching.
     pub fn get_tag_by_caption(&self, caption: &str) -> Result<Uuid> {
+        match self.find_tag(caption, None) {
+            Err(Error::NotExists(e)) => match self.split_tag_namespace(caption)? {
+                Some((category, name)) => self.find_tag(name, Some(&category)),
+                None => Err(Error::NotExists(e)),
+            },
+            result => result,
+        }
+    }
+
+    fn find_tag(&self, caption: &str, category: Option<&str>) -> Result<Uuid> {
         let tag_uuid = self
             .db
             .get()?
             .query_row(
-                "SELECT uuid FROM tag WHERE caption = ?;",
-                params![caption],
+                "SELECT uuid FROM (
+                    SELECT uuid, category, 0 AS rank FROM tag WHERE caption = ?1
+                    UNION ALL SELECT uuid, category, 1 FROM tag WHERE normalized = ?2
+                    UNION ALL SELECT tag.uuid, tag.category, 2 FROM tag_alias
+                        JOIN tag ON tag.uuid = tag_alias.tag_uuid WHERE tag_alias.normalized = ?2
+                 ) WHERE ?3 IS NULL OR category = ?3 ORDER BY rank LIMIT 1;",
+                params![caption, tools::normalize_caption(caption), category],
                 |row| Ok(row.get(0)?),
             )
             .map_err(|e| match e {
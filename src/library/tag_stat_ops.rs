@@ -0,0 +1,90 @@
+use rusqlite::params;
+
+use super::super::misc::{Result, Uuid};
+use super::{Library, RelatedTag, TagCooccurrence};
+
+impl Library {
+    // Tags with count of media, most used first. Counted from media_tag_ref rather than media_count.
+    pub fn get_tag_usage(&self, limit: Option<usize>) -> Result<Vec<(Uuid, usize)>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "SELECT tag.uuid, COUNT(media_tag_ref.media_id) AS count FROM tag
+                    LEFT JOIN media_tag_ref ON media_tag_ref.tag_uuid = tag.uuid
+                    GROUP BY tag.uuid ORDER BY count DESC, tag.caption LIMIT ?;",
+            )?
+            .query_map(params![limit.map_or(-1, |v| v as i64)], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn get_unused_tags(&self) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "SELECT uuid FROM tag
+                    WHERE NOT EXISTS(SELECT 1 FROM media_tag_ref WHERE tag_uuid = tag.uuid)
+                    ORDER BY caption;",
+            )?
+            .query_map(params![], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Pairs of tags on the same media at least min_count times, most frequent first.
+    pub fn get_tag_cooccurrence(
+        &self,
+        min_count: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<TagCooccurrence>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "SELECT a.tag_uuid, b.tag_uuid, COUNT(*) AS count FROM media_tag_ref AS a
+                    JOIN media_tag_ref AS b ON a.media_id = b.media_id AND a.tag_uuid < b.tag_uuid
+                    GROUP BY a.tag_uuid, b.tag_uuid HAVING count >= ?
+                    ORDER BY count DESC, a.tag_uuid, b.tag_uuid LIMIT ?;",
+            )?
+            .query_map(params![min_count, limit.map_or(-1, |v| v as i64)], |row| {
+                Ok(TagCooccurrence {
+                    tags: (row.get(0)?, row.get(1)?),
+                    count: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Tags appearing together with the tag, ranked by Jaccard index of their media.
+    pub fn get_related_tags(&self, tag_uuid: &Uuid, limit: usize) -> Result<Vec<RelatedTag>> {
+        self.tag_exist_guard(tag_uuid)?;
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "WITH usage(uuid, total) AS (
+                    SELECT tag_uuid, COUNT(*) FROM media_tag_ref GROUP BY tag_uuid
+                 ), together(uuid, count) AS (
+                    SELECT b.tag_uuid, COUNT(*) FROM media_tag_ref AS a
+                        JOIN media_tag_ref AS b ON a.media_id = b.media_id AND b.tag_uuid != ?1
+                        WHERE a.tag_uuid = ?1 GROUP BY b.tag_uuid
+                 )
+                 SELECT together.uuid, together.count,
+                        CAST(together.count AS REAL) / (other.total + this.total - together.count) AS score
+                    FROM together
+                    JOIN usage AS other ON other.uuid = together.uuid
+                    JOIN usage AS this ON this.uuid = ?1
+                    ORDER BY score DESC, together.count DESC LIMIT ?2;",
+            )?
+            .query_map(params![tag_uuid, limit], |row| {
+                Ok(RelatedTag {
+                    uuid: row.get(0)?,
+                    count: row.get(1)?,
+                    score: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rusqlite::params;
+
+use super::super::media::similarity::dhash_distance;
+use super::super::misc::{config, Result, Uuid};
+use super::{Library, TagSuggestion};
+
+const DHASH_PATH: &str = "json_extract(details, '$.detail.Image.dhash')";
+
+impl Library {
+    // Media added from the same folder with their weight.
+    fn same_folder_media(&self, id: u64) -> Result<HashMap<u64, f64>> {
+        let db = self.db.get()?;
+        let locations: Vec<String> = db
+            .prepare("SELECT path FROM media_location_ref WHERE media_id = ?;")?
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut neighbours = HashMap::new();
+        let mut stmt = db.prepare(
+            "SELECT media_id, path FROM media_location_ref
+                WHERE media_id != ?1 AND substr(path, 1, length(?2)) = ?2;",
+        )?;
+        for location in &locations {
+            let folder = match Path::new(location).parent() {
+                Some(folder) => folder,
+                None => continue,
+            };
+            let mut rows = stmt.query(params![id, folder.to_str()])?;
+            while let Some(row) = rows.next()? {
+                let path: String = row.get(1)?;
+                // files in sub folders share the prefix too
+                if Path::new(&path).parent() == Some(folder) {
+                    neighbours.insert(row.get(0)?, config::SAME_FOLDER_WEIGHT);
+                }
+            }
+        }
+        Ok(neighbours)
+    }
+
+    // Images looking alike with their weight, 1.0 for the same looking and less for farther ones.
+    fn similar_media(&self, id: u64) -> Result<HashMap<u64, f64>> {
+        let db = self.db.get()?;
+        let dhash: Option<String> = db
+            .query_row(
+                &format!("SELECT {} FROM media_detail WHERE id = ?;", DHASH_PATH),
+                params![id],
+                |row| row.get(0),
+            )
+            .or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                _ => Err(e),
+            })?;
+        let dhash = match dhash {
+            Some(dhash) => dhash,
+            None => return Ok(HashMap::new()),
+        };
+        let mut neighbours = HashMap::new();
+        let mut stmt = db.prepare(&format!(
+            "SELECT id, {0} FROM media_detail WHERE id != ? AND {0} IS NOT NULL;",
+            DHASH_PATH
+        ))?;
+        let mut rows = stmt.query(params![id])?;
+        while let Some(row) = rows.next()? {
+            let other: String = row.get(1)?;
+            match dhash_distance(&dhash, &other) {
+                Some(distance) if distance <= config::SIMILAR_DHASH_DISTANCE => {
+                    let weight =
+                        1.0 - distance as f64 / (config::SIMILAR_DHASH_DISTANCE + 1) as f64;
+                    neighbours.insert(row.get(0)?, weight);
+                }
+                _ => {}
+            }
+        }
+        Ok(neighbours)
+    }
+
+    // Tags on same-folder and similar media which the media does not have yet, nothing is applied.
+    // Confidence is the weighted share of these media with the tag, the highest goes first.
+    pub fn suggest_tags(&self, id: u64) -> Result<Vec<TagSuggestion>> {
+        self.media_exist_guard(id)?;
+        let mut neighbours = self.same_folder_media(id)?;
+        for (other, weight) in self.similar_media(id)? {
+            let entry = neighbours.entry(other).or_default();
+            *entry = entry.max(weight);
+        }
+        if neighbours.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let db = self.db.get()?;
+        let mut stmt = db.prepare("SELECT tag_uuid FROM media_tag_ref WHERE media_id = ?;")?;
+        let tagged: Vec<Uuid> = stmt
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let total: f64 = neighbours.values().sum();
+        let mut scores: HashMap<Uuid, (f64, usize)> = HashMap::new();
+        for (other, weight) in &neighbours {
+            let tags = stmt
+                .query_map(params![other], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<Uuid>>>()?;
+            for tag_uuid in tags.into_iter().filter(|t| !tagged.contains(t)) {
+                let (score, sources) = scores.entry(tag_uuid).or_default();
+                *score += weight;
+                *sources += 1;
+            }
+        }
+        let mut suggestions: Vec<TagSuggestion> = scores
+            .into_iter()
+            .map(|(uuid, (score, sources))| TagSuggestion {
+                uuid,
+                confidence: score / total,
+                sources,
+            })
+            .collect();
+        suggestions.sort_by(|a, b| {
+            b.confidence
+                .total_cmp(&a.confidence)
+                .then(b.sources.cmp(&a.sources))
+        });
+        Ok(suggestions)
+    }
+}
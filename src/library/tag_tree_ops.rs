@@ -0,0 +1,194 @@
+use rusqlite::params;
+
+use super::super::misc::{Error, Result, Uuid};
+use super::Library;
+
+// Tags form a DAG, a tag could have several parents, e.g. "persian" under "cat" and "long-haired".
+pub(crate) const TAG_PARENT_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tag_parent_ref(
+        id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+        tag_uuid CHAR(36) NOT NULL,
+        parent_uuid CHAR(36) NOT NULL,
+        FOREIGN KEY(tag_uuid) REFERENCES tag(uuid),
+        FOREIGN KEY(parent_uuid) REFERENCES tag(uuid),
+        CONSTRAINT unique_tag_parent UNIQUE (tag_uuid, parent_uuid)
+    );
+    ";
+
+// Tag itself and all tags under it. UNION drops duplicated rows, so it ends even on broken data.
+pub(crate) const TAG_DESCENDANTS_CTE: &str = "
+    WITH RECURSIVE descendant(uuid) AS (
+        SELECT ?1
+        UNION
+        SELECT tag_parent_ref.tag_uuid FROM tag_parent_ref
+            JOIN descendant ON tag_parent_ref.parent_uuid = descendant.uuid
+    )";
+
+const TAG_ANCESTORS_CTE: &str = "
+    WITH RECURSIVE ancestor(uuid) AS (
+        SELECT ?1
+        UNION
+        SELECT tag_parent_ref.parent_uuid FROM tag_parent_ref
+            JOIN ancestor ON tag_parent_ref.tag_uuid = ancestor.uuid
+    )";
+
+impl Library {
+    pub fn add_tag_parent(&mut self, tag_uuid: &Uuid, parent_uuid: &Uuid) -> Result<()> {
+        self.tag_exist_guard(tag_uuid)?;
+        self.tag_exist_guard(parent_uuid)?;
+        if self.get_tag_descendants(tag_uuid)?.contains(parent_uuid) || tag_uuid == parent_uuid {
+            return Err(Error::Cycle(format!(
+                "Tag {} under its descendant {}",
+                tag_uuid, parent_uuid
+            )));
+        }
+        self.db.get()?.execute(
+            "INSERT OR IGNORE INTO tag_parent_ref (tag_uuid, parent_uuid) VALUES (?, ?);",
+            params![tag_uuid, parent_uuid],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_tag_parent(&mut self, tag_uuid: &Uuid, parent_uuid: &Uuid) -> Result<()> {
+        self.db.get()?.execute(
+            "DELETE FROM tag_parent_ref WHERE tag_uuid = ? AND parent_uuid = ?;",
+            params![tag_uuid, parent_uuid],
+        )?;
+        Ok(())
+    }
+
+    // Replace all parents of the tag, None to make it a root tag.
+    pub fn move_tag(&mut self, tag_uuid: &Uuid, parent_uuid: Option<&Uuid>) -> Result<()> {
+        self.tag_exist_guard(tag_uuid)?;
+        let mut db = self.db.get()?;
+        if let Some(parent_uuid) = parent_uuid {
+            self.tag_exist_guard(parent_uuid)?;
+            if self.get_tag_descendants(tag_uuid)?.contains(parent_uuid) || tag_uuid == parent_uuid
+            {
+                return Err(Error::Cycle(format!(
+                    "Tag {} under its descendant {}",
+                    tag_uuid, parent_uuid
+                )));
+            }
+        }
+        let tx = db.transaction()?;
+        tx.execute(
+            "DELETE FROM tag_parent_ref WHERE tag_uuid = ?;",
+            params![tag_uuid],
+        )?;
+        if let Some(parent_uuid) = parent_uuid {
+            tx.execute(
+                "INSERT INTO tag_parent_ref (tag_uuid, parent_uuid) VALUES (?, ?);",
+                params![tag_uuid, parent_uuid],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Create tags along the path like "animal/cat/persian" and return the last one.
+    pub fn create_tag_path(&mut self, path: &str) -> Result<Uuid> {
+        let mut parent: Option<Uuid> = None;
+        for caption in path.split('/').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let uuid = self.create_tag(caption.to_string(), None)?;
+            if let Some(parent) = &parent {
+                self.add_tag_parent(&uuid, parent)?;
+            }
+            parent = Some(uuid);
+        }
+        parent.ok_or_else(|| Error::NotExists(format!("Tag in path \"{}\"", path)))
+    }
+
+    pub fn get_tag_children(&self, tag_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare("SELECT tag_uuid FROM tag_parent_ref WHERE parent_uuid = ?;")?
+            .query_map(params![tag_uuid], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn get_tag_parents(&self, tag_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare("SELECT parent_uuid FROM tag_parent_ref WHERE tag_uuid = ?;")?
+            .query_map(params![tag_uuid], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // Tags without parent.
+    pub fn get_root_tags(&self) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(
+                "SELECT uuid FROM tag WHERE uuid NOT IN (SELECT tag_uuid FROM tag_parent_ref)
+                    ORDER BY caption;",
+            )?
+            .query_map(params![], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // All tags under the tag, excluding itself.
+    pub fn get_tag_descendants(&self, tag_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(&format!(
+                "{} SELECT uuid FROM descendant WHERE uuid != ?1;",
+                TAG_DESCENDANTS_CTE
+            ))?
+            .query_map(params![tag_uuid], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // All tags above the tag, excluding itself.
+    pub fn get_tag_ancestors(&self, tag_uuid: &Uuid) -> Result<Vec<Uuid>> {
+        Ok(self
+            .db
+            .get()?
+            .prepare(&format!(
+                "{} SELECT uuid FROM ancestor WHERE uuid != ?1;",
+                TAG_ANCESTORS_CTE
+            ))?
+            .query_map(params![tag_uuid], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // With include_descendants, media tagged with any tag under it are matched too.
+    pub fn query_media_by_tag(
+        &self,
+        tag_uuid: &Uuid,
+        include_descendants: bool,
+    ) -> Result<Vec<u64>> {
+        let sql = if include_descendants {
+            format!(
+                "{} SELECT DISTINCT media_id FROM media_tag_ref
+                    WHERE tag_uuid IN (SELECT uuid FROM descendant) ORDER BY media_id;",
+                TAG_DESCENDANTS_CTE
+            )
+        } else {
+            "SELECT media_id FROM media_tag_ref WHERE tag_uuid = ?1 ORDER BY media_id;".to_string()
+        };
+        Ok(self
+            .db
+            .get()?
+            .prepare(&sql)?
+            .query_map(params![tag_uuid], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // With aggregated, media tagged with the tag or any tag under it are counted once.
+    pub fn get_tag_media_count(&self, tag_uuid: &Uuid, aggregated: bool) -> Result<usize> {
+        self.tag_exist_guard(tag_uuid)?;
+        if !aggregated {
+            return Ok(self.db.get()?.query_row(
+                "SELECT media_count FROM tag WHERE uuid = ?;",
+                params![tag_uuid],
+                |row| row.get(0),
+            )?);
+        }
+        Ok(self.query_media_by_tag(tag_uuid, true)?.len())
+    }
+}
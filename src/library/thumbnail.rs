@@ -1,137 +1,625 @@
 use std::{
+    collections::{BinaryHeap, HashMap},
+    future::Future,
     io::{Read, Write},
+    pin::Pin,
     str,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::mpsc::{channel, Receiver, RecvError, Sender, TryRecvError},
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
 };
 
 use rusqlite::{params, DatabaseName};
 
-use super::super::misc::{config, Error, Result};
-use super::Library;
-use std::sync::mpsc::{channel, Receiver};
+use super::super::media::{Media, MediaType, ThumbnailFormat, ThumbnailSpec};
+use super::super::misc::tools::has_column;
+use super::super::misc::{config, Error, Result, Uuid};
+use super::sheet_ops::SERIES_SHEET_SCHEMA;
+use super::{Library, SQLite, ThumbnailPriority, ThumbnailRequest};
 
-macro_rules! unwrap_or_send_err {
-    ( $result:expr, $tx:expr, $rx:expr) => {
-        match $result {
-            Ok(v) => v,
-            Err(e) => {
-                $tx.send(Err(e)).unwrap();
-                return $rx;
-            }
+type ThumbnailKey = (String, ThumbnailSpec);
+
+// Requests of the same media and spec share one job, so the thumbnail is made only once.
+#[derive(Default)]
+pub(crate) struct ThumbnailQueue {
+    pending: BinaryHeap<PendingThumbnail>,
+    jobs: HashMap<ThumbnailKey, ThumbnailJob>,
+    seq: u64,
+}
+
+struct ThumbnailJob {
+    media: Option<Media>, // taken by the worker
+    priority: ThumbnailPriority,
+    force: bool,
+    waiters: Vec<ThumbnailWaiter>,
+}
+
+struct ThumbnailWaiter {
+    tx: Sender<Result<Vec<u8>>>,
+    cancelled: Arc<AtomicBool>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+// Raising priority of a job pushes it again, the stale one is skipped when popped.
+#[derive(PartialEq, Eq)]
+struct PendingThumbnail {
+    priority: ThumbnailPriority,
+    seq: u64,
+    key: ThumbnailKey,
+}
+
+impl Ord for PendingThumbnail {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // higher priority first, then first come first served.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for PendingThumbnail {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Debug for ThumbnailQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThumbnailQueue")
+            .field("pending", &self.pending.len())
+            .field("jobs", &self.jobs.len())
+            .finish()
+    }
+}
+
+// A poisoned lock only means another worker panicked, the data is still consistent.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Errors are not cloneable, so waiters except the first one get a copy of the message.
+fn share_error(err: &Error) -> Error {
+    match err {
+        Error::NotExists(s) => Error::NotExists(s.clone()),
+        Error::NoThumbnail => Error::NoThumbnail,
+        Error::Cancelled => Error::Cancelled,
+        Error::MediaDecode(s) => Error::MediaDecode(s.clone()),
+        e => Error::Other(e.to_string()),
+    }
+}
+
+impl ThumbnailWaiter {
+    fn send(self, result: Result<Vec<u8>>) {
+        let result = if self.cancelled.load(Ordering::SeqCst) {
+            Err(Error::Cancelled)
+        } else {
+            result
+        };
+        let _ = self.tx.send(result); // receiver may be dropped
+        if let Some(waker) = lock(&self.waker).take() {
+            waker.wake();
         }
-    };
+    }
 }
 
-impl Library {
-    fn make_thumbnail_no_check(&mut self, hash: &str) -> Receiver<Result<Vec<u8>>> {
+impl ThumbnailRequest {
+    fn new() -> (ThumbnailRequest, ThumbnailWaiter) {
         let (tx, rx) = channel();
+        let request = ThumbnailRequest {
+            rx,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            waker: Arc::new(Mutex::new(None)),
+        };
+        let waiter = ThumbnailWaiter {
+            tx,
+            cancelled: request.cancelled.clone(),
+            waker: request.waker.clone(),
+        };
+        (request, waiter)
+    }
 
-        let media = self.get_media(match self.get_media_id(hash) {
-            Some(id) => id,
-            None => {
-                tx.send(Err(Error::NotExists(format!("Media with hash {}", hash))))
-                    .unwrap();
-                return rx;
-            }
-        });
-        let media = unwrap_or_send_err!(media, tx, rx);
-        let thumbnail_db = self.thumbnail_db.clone();
+    fn failed(err: Error) -> ThumbnailRequest {
+        let (request, waiter) = ThumbnailRequest::new();
+        waiter.send(Err(err));
+        request
+    }
 
-        self.thread_pool.execute(move || {
-            let result = (|| {
-                let mut buffer: Vec<u8> = Vec::new();
-                media.get_thumbnail(
-                    &mut buffer,
-                    config::THUMBNAIL_SIZE.0,
-                    config::THUMBNAIL_SIZE.1,
-                )?;
-                let thumb_size = buffer.len();
-                if thumb_size <= 0 {
-                    return Err(Error::NoThumbnail);
-                }
-                println!(
-                    "Generated thumbnail for {} with size {} bytes.",
-                    media.hash, thumb_size
-                );
-                let thumbnail_db = thumbnail_db.get()?;
-                thumbnail_db.execute(
-                    "INSERT INTO thumbnail (hash, image, size) VALUES (?, ZEROBLOB(?), ?);",
-                    params![media.hash, thumb_size, thumb_size],
-                )?;
-                let row_id = thumbnail_db.last_insert_rowid();
-                println!("Thumbnail size: {} bytes.", thumb_size);
-                let mut blob = thumbnail_db.blob_open(
-                    DatabaseName::Main,
-                    "thumbnail",
-                    "image",
-                    row_id,
-                    false,
-                )?;
-                let wrote_size = blob.write(&buffer)?;
-                assert_eq!(thumb_size, wrote_size); //  hope not panic
-                Ok(buffer)
-            })();
-            let _ = tx.send(result); // drop result
-        });
-        rx
+    // The thumbnail is not made if all requests of it are cancelled before started.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
     }
 
-    fn get_thumbnail_no_check(&self, hash: &str) -> Receiver<Result<Vec<u8>>> {
-        let (tx, rx) = channel();
-        let thumbnail_db = self.thumbnail_db.clone();
-        let hash = hash.to_string();
-        self.thread_pool.execute(move || {
-            let result = (|| -> Result<Vec<u8>> {
-                let thumbnail_db = thumbnail_db.get()?;
-                let id = thumbnail_db.query_row(
-                    "SELECT id FROM thumbnail WHERE hash = ?;",
-                    params![hash],
-                    |row| Ok(row.get(0)?),
-                )?;
-                let mut buffer: Vec<u8> = Vec::new();
-                let mut blob =
-                    thumbnail_db.blob_open(DatabaseName::Main, "thumbnail", "image", id, true)?;
-                let read_size = blob.read_to_end(&mut buffer)?;
-                let thumb_size: usize = thumbnail_db.query_row(
-                    "SELECT size FROM thumbnail WHERE hash = ?;",
-                    params![hash],
-                    |row| Ok(row.get(0)?),
-                )?;
-                assert_eq!(read_size, thumb_size);
-                Ok(buffer)
-            })();
-            let _ = tx.send(result); // drop result
-        });
-        rx
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
     }
 
-    pub fn get_thumbnail(&mut self, id: u64) -> Receiver<Result<Vec<u8>>> {
-        let is_thumbnailed = self.is_thumbnailed(id);
-        let hash = &match self.get_media_hash(id) {
+    pub fn wait(self) -> Result<Vec<u8>> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        self.rx.recv()?
+    }
+
+    // None if not finished yet.
+    pub fn try_wait(&self) -> Option<Result<Vec<u8>>> {
+        if self.is_cancelled() {
+            return Some(Err(Error::Cancelled));
+        }
+        match self.rx.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(RecvError.into())),
+        }
+    }
+
+    pub fn into_receiver(self) -> Receiver<Result<Vec<u8>>> {
+        self.rx
+    }
+}
+
+impl Future for ThumbnailRequest {
+    type Output = Result<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(result) = self.try_wait() {
+            return Poll::Ready(result);
+        }
+        *lock(&self.waker) = Some(cx.waker().clone());
+        // the result may be sent before the waker is set.
+        match self.try_wait() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+pub(crate) const THUMBNAIL_SCHEMA: &str = "
+    CREATE TABLE thumbnail(
+        id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL UNIQUE,
+        hash CHAR(32) NOT NULL,
+        width INTEGER NOT NULL,
+        height INTEGER NOT NULL,
+        format CHAR(4) NOT NULL,
+        quality INTEGER NOT NULL,
+        animated BOOLEAN NOT NULL DEFAULT FALSE,
+        image BLOB,
+        size INTEGER NOT NULL,
+        time_access TIMESTAMP NOT NULL DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f+00:00', 'NOW')),
+        UNIQUE(hash, width, height, format, animated)
+    );
+    CREATE INDEX thumbnail_access ON thumbnail(time_access);
+    ";
+
+// Thumbnails are only cache, so the table of old libraries is just recreated.
+pub(crate) fn migrate_thumbnail_db(thumbnail_db: &SQLite) -> Result<()> {
+    let thumbnail_db = thumbnail_db.get()?;
+    if !has_column(&thumbnail_db, "thumbnail", "time_access")?
+        || !has_column(&thumbnail_db, "thumbnail", "animated")?
+    {
+        thumbnail_db.execute_batch(&format!(
+            "DROP TABLE IF EXISTS thumbnail; {}",
+            THUMBNAIL_SCHEMA
+        ))?;
+    }
+    if !has_column(&thumbnail_db, "series_sheet", "time_access")?
+        || !has_column(&thumbnail_db, "series_sheet", "captions")?
+    {
+        thumbnail_db.execute_batch(&format!(
+            "DROP TABLE IF EXISTS series_sheet; {}",
+            SERIES_SHEET_SCHEMA
+        ))?;
+    }
+    if !has_column(&thumbnail_db, "metadata", "budget")? {
+        thumbnail_db.execute("ALTER TABLE metadata ADD COLUMN budget INTEGER;", params![])?;
+    }
+    Ok(())
+}
+
+fn get_budget(thumbnail_db: &rusqlite::Connection) -> Result<usize> {
+    let budget: Option<usize> =
+        thumbnail_db.query_row("SELECT budget FROM metadata;", params![], |row| row.get(0))?;
+    Ok(budget.unwrap_or(config::THUMBNAIL_BUDGET))
+}
+
+// Total bytes of cached thumbnails and series sheets.
+fn get_usage(thumbnail_db: &rusqlite::Connection) -> Result<usize> {
+    Ok(thumbnail_db.query_row(
+        "SELECT (SELECT IFNULL(SUM(size), 0) FROM thumbnail)
+            + (SELECT IFNULL(SUM(size), 0) FROM series_sheet);",
+        params![],
+        |row| row.get(0),
+    )?)
+}
+
+// Drop least recently used thumbnails and series sheets until the cache fits in the budget.
+// Returns the count of evicted ones.
+pub(crate) fn evict_over_budget(thumbnail_db: &rusqlite::Connection) -> Result<usize> {
+    let budget = get_budget(thumbnail_db)?;
+    let mut usage = get_usage(thumbnail_db)?;
+    if usage <= budget {
+        return Ok(0);
+    }
+    let mut to_evict = Vec::new();
+    let mut stmt = thumbnail_db.prepare(
+        "SELECT 'thumbnail', id, size, time_access FROM thumbnail
+            UNION ALL SELECT 'series_sheet', id, size, time_access FROM series_sheet
+            ORDER BY time_access, id;",
+    )?;
+    let mut rows = stmt.query(params![])?;
+    while let Some(row) = rows.next()? {
+        if usage <= budget {
+            break;
+        }
+        let (table, id, size): (String, i64, usize) = (row.get(0)?, row.get(1)?, row.get(2)?);
+        to_evict.push((table, id));
+        usage -= size;
+    }
+    for (table, id) in to_evict.iter() {
+        thumbnail_db.execute(&format!("DELETE FROM {} WHERE id = ?;", table), params![id])?;
+    }
+    Ok(to_evict.len())
+}
+
+fn read_thumbnail(
+    thumbnail_db: &rusqlite::Connection,
+    hash: &str,
+    spec: &ThumbnailSpec,
+) -> Result<Option<Vec<u8>>> {
+    let (id, thumb_size): (i64, usize) = match thumbnail_db.query_row(
+        "SELECT id, size FROM thumbnail
+            WHERE hash = ? AND width = ? AND height = ? AND format = ? AND animated = ?
+                AND quality = ?;",
+        params![
+            hash,
+            spec.width,
+            spec.height,
+            spec.format,
+            spec.animated,
+            spec.quality
+        ],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ) {
+        Ok(v) => v,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut blob = thumbnail_db.blob_open(DatabaseName::Main, "thumbnail", "image", id, true)?;
+    let read_size = blob.read_to_end(&mut buffer)?;
+    if read_size != thumb_size {
+        return Err(Error::NotMatch(format!("Size of thumbnail {}", id)));
+    }
+    thumbnail_db.execute(
+        "UPDATE thumbnail SET time_access = STRFTIME('%Y-%m-%d %H:%M:%f+00:00', 'NOW')
+            WHERE id = ?;",
+        params![id],
+    )?;
+    Ok(Some(buffer))
+}
+
+fn make_thumbnail(
+    thumbnail_db: &rusqlite::Connection,
+    media: &Media,
+    spec: &ThumbnailSpec,
+) -> Result<Vec<u8>> {
+    let mut buffer: Vec<u8> = Vec::new();
+    media.get_thumbnail(&mut buffer, spec)?;
+    let thumb_size = buffer.len();
+    if thumb_size == 0 {
+        return Err(Error::NoThumbnail);
+    }
+    // replace the one with another quality if existed.
+    thumbnail_db.execute(
+        "INSERT OR REPLACE INTO thumbnail
+            (hash, width, height, format, quality, animated, image, size)
+            VALUES (?, ?, ?, ?, ?, ?, ZEROBLOB(?), ?);",
+        params![
+            media.hash,
+            spec.width,
+            spec.height,
+            spec.format,
+            spec.quality,
+            spec.animated,
+            thumb_size,
+            thumb_size
+        ],
+    )?;
+    let row_id = thumbnail_db.last_insert_rowid();
+    let mut blob =
+        thumbnail_db.blob_open(DatabaseName::Main, "thumbnail", "image", row_id, false)?;
+    blob.write_all(&buffer)?;
+    evict_over_budget(thumbnail_db)?;
+    Ok(buffer)
+}
+
+// Every queued job runs this once, taking the request with highest priority instead of its own.
+fn run_thumbnail_job(queue: Arc<Mutex<ThumbnailQueue>>, thumbnail_db: SQLite) {
+    let (key, media, force) = loop {
+        let mut queue = lock(&queue);
+        let pending = match queue.pending.pop() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let job = match queue.jobs.get_mut(&pending.key) {
+            Some(job) if job.media.is_some() && job.priority == pending.priority => job,
+            _ => continue, // stale one
+        };
+        let (cancelled, waiters) = job
+            .waiters
+            .drain(..)
+            .partition(|w| w.cancelled.load(Ordering::SeqCst));
+        job.waiters = waiters;
+        cancelled
+            .into_iter()
+            .for_each(|w: ThumbnailWaiter| w.send(Err(Error::Cancelled)));
+        if job.waiters.is_empty() {
+            queue.jobs.remove(&pending.key);
+            return;
+        }
+        let media = job.media.take().unwrap();
+        break (pending.key, media, job.force);
+    };
+    let (hash, spec) = &key;
+    let result = (|| {
+        let thumbnail_db = thumbnail_db.get()?;
+        if !force {
+            if let Some(buffer) = read_thumbnail(&thumbnail_db, hash, spec)? {
+                return Ok(buffer);
+            }
+        }
+        make_thumbnail(&thumbnail_db, &media, spec)
+    })();
+    let job = lock(&queue).jobs.remove(&key);
+    if let Some(job) = job {
+        let mut waiters = job.waiters.into_iter();
+        let first = waiters.next();
+        for waiter in waiters {
+            waiter.send(match &result {
+                Ok(buffer) => Ok(buffer.clone()),
+                Err(e) => Err(share_error(e)),
+            });
+        }
+        if let Some(waiter) = first {
+            waiter.send(result);
+        }
+    }
+}
+
+impl Library {
+    // Queue a thumbnail request, requests of the same media and spec are merged.
+    // With force the cached one is ignored and made again.
+    fn queue_thumbnail(
+        &self,
+        id: u64,
+        spec: &ThumbnailSpec,
+        priority: ThumbnailPriority,
+        force: bool,
+    ) -> ThumbnailRequest {
+        let hash = match self.get_media_hash(id) {
             Some(hash) => hash,
-            None => panic!("Get media hash failed."),
+            None => {
+                return ThumbnailRequest::failed(Error::NotExists(format!("Media with id {}", id)))
+            }
         };
-        if is_thumbnailed {
-            self.get_thumbnail_no_check(hash)
-        } else {
-            self.make_thumbnail_no_check(hash)
+        let (request, waiter) = ThumbnailRequest::new();
+        let key = (hash, spec.clone());
+        let mut queue = lock(&self.thumbnail_queue);
+        queue.seq += 1;
+        let seq = queue.seq;
+        if let Some(job) = queue.jobs.get_mut(&key) {
+            job.waiters.push(waiter);
+            if job.media.is_some() {
+                job.force |= force;
+                if priority > job.priority {
+                    job.priority = priority;
+                    queue.pending.push(PendingThumbnail { priority, seq, key });
+                }
+            }
+            return request;
+        }
+        let mut media = match self.get_media(id) {
+            Ok(media) => media,
+            Err(e) => {
+                waiter.send(Err(e));
+                return request;
+            }
+        };
+        if let MediaType::URL = media.kind {
+            // url media is thumbnailed from the screenshot attached with its snapshot.
+            if let Some(screenshot) = media.get_url_snapshot().and_then(|s| s.screenshot.clone()) {
+                media.filepath = self
+                    .get_snapshot_path_by_hash(&screenshot)
+                    .to_string_lossy()
+                    .to_string();
+            }
         }
+        queue.jobs.insert(
+            key.clone(),
+            ThumbnailJob {
+                media: Some(media),
+                priority,
+                force,
+                waiters: vec![waiter],
+            },
+        );
+        queue.pending.push(PendingThumbnail { priority, seq, key });
+        drop(queue);
+
+        let queue = self.thumbnail_queue.clone();
+        let thumbnail_db = self.thumbnail_db.clone();
+        self.thread_pool
+            .execute(move || run_thumbnail_job(queue, thumbnail_db));
+        request
+    }
+
+    // Get thumbnail in given size and format, made if not cached yet.
+    // The request could be cancelled or awaited as a future.
+    pub fn request_thumbnail(
+        &self,
+        id: u64,
+        spec: &ThumbnailSpec,
+        priority: ThumbnailPriority,
+    ) -> ThumbnailRequest {
+        self.queue_thumbnail(id, spec, priority, false)
+    }
+
+    pub fn get_thumbnail(&mut self, id: u64) -> Receiver<Result<Vec<u8>>> {
+        self.get_thumbnail_sized(id, &ThumbnailSpec::default())
+    }
+
+    pub fn get_thumbnail_sized(
+        &mut self,
+        id: u64,
+        spec: &ThumbnailSpec,
+    ) -> Receiver<Result<Vec<u8>>> {
+        self.request_thumbnail(id, spec, ThumbnailPriority::Normal)
+            .into_receiver()
     }
 
     pub fn make_thumbnail(&mut self, id: u64) -> Receiver<Result<Vec<u8>>> {
-        let is_thumbnailed = self.is_thumbnailed(id);
-        let hash = &match self.get_media_hash(id) {
+        self.request_thumbnail(id, &ThumbnailSpec::default(), ThumbnailPriority::Low)
+            .into_receiver()
+    }
+
+    // Drop cached thumbnails and make them again, e.g. after the way of thumbnailing changed.
+    // The returned receiver is for the default one, other cached sizes are made in background.
+    pub fn regenerate_thumbnail(&mut self, id: u64) -> Receiver<Result<Vec<u8>>> {
+        let hash = match self.get_media_hash(id) {
             Some(hash) => hash,
-            None => panic!("Get media hash failed."),
+            None => {
+                return ThumbnailRequest::failed(Error::NotExists(format!("Media with id {}", id)))
+                    .into_receiver()
+            }
         };
-        println!(
-            "Make thumbnail for id {}, with is_thumbnailed = {}",
-            id, is_thumbnailed
-        );
-        if !is_thumbnailed {
-            self.make_thumbnail_no_check(hash)
-        } else {
-            self.get_thumbnail_no_check(hash)
+        let default_spec = ThumbnailSpec::default();
+        let specs = match self.get_thumbnail_specs(Some(&hash)) {
+            Ok(specs) => specs,
+            Err(e) => return ThumbnailRequest::failed(e).into_receiver(),
+        };
+        for (_, spec) in specs.iter().filter(|(_, spec)| spec != &default_spec) {
+            self.queue_thumbnail(id, spec, ThumbnailPriority::Low, true);
         }
+        self.queue_thumbnail(id, &default_spec, ThumbnailPriority::Normal, true)
+            .into_receiver()
+    }
+
+    // Regenerate all cached thumbnails in background, return the count of queued thumbnails.
+    // Use wait_workers to wait for them.
+    pub fn regenerate_thumbnails(&mut self) -> Result<usize> {
+        let specs = self.get_thumbnail_specs(None)?;
+        let mut count = 0;
+        for (hash, spec) in specs {
+            match self.get_media_id(&hash) {
+                Some(id) => {
+                    self.queue_thumbnail(id, &spec, ThumbnailPriority::Low, true);
+                    count += 1;
+                }
+                None => self.remove_thumbnail(&hash)?,
+            }
+        }
+        Ok(count)
+    }
+
+    // List cached thumbnails of the media with the hash, or all cached thumbnails.
+    pub fn get_thumbnail_specs(&self, hash: Option<&str>) -> Result<Vec<(String, ThumbnailSpec)>> {
+        let thumbnail_db = self.thumbnail_db.get()?;
+        let mut stmt = thumbnail_db.prepare(
+            "SELECT hash, width, height, format, quality, animated FROM thumbnail
+                WHERE ?1 IS NULL OR hash = ?1;",
+        )?;
+        let specs = stmt
+            .query_map(params![hash], |row| {
+                Ok((
+                    row.get(0)?,
+                    ThumbnailSpec {
+                        width: row.get(1)?,
+                        height: row.get(2)?,
+                        format: row.get::<_, ThumbnailFormat>(3)?,
+                        quality: row.get(4)?,
+                        animated: row.get(5)?,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(specs)
+    }
+
+    pub(crate) fn remove_thumbnail(&self, hash: &str) -> Result<()> {
+        self.thumbnail_db
+            .get()?
+            .execute("DELETE FROM thumbnail WHERE hash = ?;", params![hash])?;
+        Ok(())
+    }
+
+    // Remove thumbnails of media and sheets of series which are not in library any more.
+    pub fn remove_orphan_thumbnails(&self) -> Result<usize> {
+        let hashes: Vec<String> = self
+            .thumbnail_db
+            .get()?
+            .prepare("SELECT DISTINCT hash FROM thumbnail;")?
+            .query_map(params![], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut count = 0;
+        for hash in hashes {
+            if self.get_media_id(&hash).is_none() {
+                count += self
+                    .thumbnail_db
+                    .get()?
+                    .execute("DELETE FROM thumbnail WHERE hash = ?;", params![hash])?;
+            }
+        }
+        let series: Vec<Uuid> = self
+            .thumbnail_db
+            .get()?
+            .prepare("SELECT DISTINCT series_uuid FROM series_sheet;")?
+            .query_map(params![], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for uuid in series {
+            if !self.is_series_existed(&uuid) {
+                count += self.thumbnail_db.get()?.execute(
+                    "DELETE FROM series_sheet WHERE series_uuid = ?;",
+                    params![uuid],
+                )?;
+            }
+        }
+        Ok(count)
+    }
+
+    // None to reset to the default budget. Thumbnails over the budget are evicted immediately.
+    pub fn set_thumbnail_budget(&mut self, budget: Option<usize>) -> Result<()> {
+        let thumbnail_db = self.thumbnail_db.get()?;
+        thumbnail_db.execute("UPDATE metadata SET budget = ?;", params![budget])?;
+        evict_over_budget(&thumbnail_db)?;
+        Ok(())
+    }
+
+    pub fn get_thumbnail_budget(&self) -> Result<usize> {
+        get_budget(&*self.thumbnail_db.get()?)
+    }
+
+    // Total bytes of cached thumbnails and series sheets.
+    pub fn get_thumbnail_usage(&self) -> Result<usize> {
+        get_usage(&*self.thumbnail_db.get()?)
+    }
+
+    // Remove orphans, evict thumbnails over budget and vacuum the database.
+    // Returns reclaimed bytes of the database file.
+    pub fn compact_thumbnails(&mut self) -> Result<usize> {
+        self.remove_orphan_thumbnails()?;
+        let thumbnail_db = self.thumbnail_db.get()?;
+        evict_over_budget(&thumbnail_db)?;
+        let db_size = |db: &rusqlite::Connection| -> Result<usize> {
+            Ok(db.query_row(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size();",
+                params![],
+                |row| row.get(0),
+            )?)
+        };
+        let before = db_size(&thumbnail_db)?;
+        thumbnail_db.execute_batch("VACUUM;")?;
+        let after = db_size(&thumbnail_db)?;
+        Ok(before.saturating_sub(after))
     }
 
     pub fn wait_thumbnail(rx: Receiver<Result<Vec<u8>>>) -> Result<Vec<u8>> {
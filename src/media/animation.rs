@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::{BufReader, Write};
+
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::{AnimationDecoder, Delay, DynamicImage, Frame, ImageBuffer, ImageFormat, RgbaImage};
+use image_webp::WebPDecoder;
+
+use super::super::misc::{config, Error, Result};
+use super::{ThumbnailFormat, ThumbnailSpec};
+
+// Frame with its delay in milliseconds.
+type Frames<'a> = Box<dyn Iterator<Item = Result<(RgbaImage, u32)>> + 'a>;
+
+fn webp_error(err: image_webp::DecodingError) -> Error {
+    Error::MediaDecode(format!("Decode webp failed: {}", err))
+}
+
+fn open_webp(media_path: &str) -> Result<WebPDecoder<BufReader<File>>> {
+    WebPDecoder::new(BufReader::new(File::open(media_path)?)).map_err(webp_error)
+}
+
+// Decoder in image crate knows nothing about lossless and animated webp.
+pub(crate) fn webp_dimensions(media_path: &str) -> Result<(u32, u32)> {
+    Ok(open_webp(media_path)?.dimensions())
+}
+
+fn webp_buffer(buffer: Vec<u8>, (width, height): (u32, u32), has_alpha: bool) -> Result<RgbaImage> {
+    let image = if has_alpha {
+        ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8)
+    } else {
+        ImageBuffer::from_raw(width, height, buffer).map(DynamicImage::ImageRgb8)
+    };
+    image
+        .map(|image| image.to_rgba8())
+        .ok_or_else(|| Error::MediaDecode("Broken webp frame.".to_string()))
+}
+
+// The first frame for animated one.
+pub(crate) fn decode_webp(media_path: &str) -> Result<DynamicImage> {
+    let mut decoder = open_webp(media_path)?;
+    let mut buffer = vec![0; decoder.output_buffer_size().ok_or(Error::NoneError)?];
+    decoder.read_image(&mut buffer).map_err(webp_error)?;
+    let image = webp_buffer(buffer, decoder.dimensions(), decoder.has_alpha())?;
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+// Browsers play frames without delay in 10 fps, so do we.
+fn normalize_delay(delay: u32) -> u32 {
+    if delay <= 10 {
+        100
+    } else {
+        delay
+    }
+}
+
+fn read_frames<'a>(media_path: &str, format: ImageFormat) -> Result<Option<Frames<'a>>> {
+    match format {
+        ImageFormat::Gif => {
+            let decoder = GifDecoder::new(BufReader::new(File::open(media_path)?))?;
+            Ok(Some(Box::new(decoder.into_frames().map(|frame| {
+                let frame = frame?;
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                Ok((frame.into_buffer(), numer / denom.max(1)))
+            }))))
+        }
+        ImageFormat::WebP => {
+            let mut decoder = open_webp(media_path)?;
+            if !decoder.is_animated() {
+                return Ok(None);
+            }
+            let size = decoder.output_buffer_size().ok_or(Error::NoneError)?;
+            Ok(Some(Box::new(std::iter::from_fn(move || {
+                let mut buffer = vec![0; size];
+                match decoder.read_frame(&mut buffer) {
+                    Ok(delay) => Some(
+                        webp_buffer(buffer, decoder.dimensions(), decoder.has_alpha())
+                            .map(|image| (image, delay)),
+                    ),
+                    Err(image_webp::DecodingError::NoMoreFrames) => None,
+                    Err(e) => Some(Err(webp_error(e))),
+                }
+            }))))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Count of frames and total duration in milliseconds, None for still images.
+pub(crate) fn read_animation_info(
+    media_path: &str,
+    format: ImageFormat,
+) -> Result<Option<(u32, u64)>> {
+    if let ImageFormat::WebP = format {
+        // webp knows it without decoding frames
+        let decoder = open_webp(media_path)?;
+        return Ok(match decoder.is_animated() && decoder.num_frames() > 1 {
+            true => Some((decoder.num_frames(), decoder.loop_duration())),
+            false => None,
+        });
+    }
+    let frames = match read_frames(media_path, format)? {
+        Some(frames) => frames,
+        None => return Ok(None),
+    };
+    let (mut count, mut duration) = (0, 0);
+    for frame in frames {
+        let (_, delay) = frame?;
+        count += 1;
+        duration += normalize_delay(delay) as u64;
+    }
+    Ok(if count > 1 {
+        Some((count, duration))
+    } else {
+        None
+    })
+}
+
+// Take first frames of the animation in reduced fps, frames dropped are merged into the previous one.
+pub(crate) fn read_preview_frames(
+    media_path: &str,
+    format: ImageFormat,
+    spec: &ThumbnailSpec,
+) -> Result<Vec<(RgbaImage, u32)>> {
+    let frames = match read_frames(media_path, format)? {
+        Some(frames) => frames,
+        None => return Ok(vec![]),
+    };
+    let interval = 1000 / config::ANIMATED_THUMBNAIL_FPS;
+    let mut preview: Vec<(RgbaImage, u32)> = vec![];
+    let mut elapsed = interval;
+    for frame in frames {
+        let (buffer, delay) = frame?;
+        let delay = normalize_delay(delay);
+        if elapsed < interval {
+            if let Some((_, last)) = preview.last_mut() {
+                *last += delay;
+            }
+            elapsed += delay;
+            continue;
+        }
+        if preview.len() >= config::ANIMATED_THUMBNAIL_FRAMES {
+            break;
+        }
+        let thumb = DynamicImage::ImageRgba8(buffer).thumbnail(spec.width, spec.height);
+        preview.push((thumb.to_rgba8(), delay));
+        elapsed = delay;
+    }
+    Ok(preview)
+}
+
+fn write_chunk<W: Write>(w: &mut W, fourcc: &[u8], data: &[u8]) -> std::io::Result<()> {
+    w.write_all(fourcc)?;
+    w.write_all(&(data.len() as u32).to_le_bytes())?;
+    w.write_all(data)?;
+    if data.len() % 2 == 1 {
+        w.write_all(&[0])?; // chunks are padded to even size
+    }
+    Ok(())
+}
+
+// image-webp could only encode still lossless images, so frames are wrapped into ANMF chunks here.
+fn encode_webp<W: Write>(frames: Vec<(RgbaImage, u32)>, image: &mut W) -> Result<()> {
+    let (width, height) = frames[0].0.dimensions();
+    let mut vp8x = vec![0x10 | 0x02, 0, 0, 0]; // alpha and animation
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    let mut body = b"WEBP".to_vec();
+    write_chunk(&mut body, b"VP8X", &vp8x)?;
+    write_chunk(&mut body, b"ANIM", &[0, 0, 0, 0, 0, 0])?; // transparent background, infinite loop
+
+    for (frame, delay) in frames {
+        let mut still = vec![];
+        image_webp::WebPEncoder::new(&mut still)
+            .encode(
+                &frame,
+                frame.width(),
+                frame.height(),
+                image_webp::ColorType::Rgba8,
+            )
+            .map_err(|e| Error::MediaDecode(format!("Encode webp failed: {}", e)))?;
+        let mut anmf = vec![0; 6]; // offset of x and y
+        anmf.extend_from_slice(&(frame.width() - 1).to_le_bytes()[..3]);
+        anmf.extend_from_slice(&(frame.height() - 1).to_le_bytes()[..3]);
+        anmf.extend_from_slice(&delay.min(0xffffff).to_le_bytes()[..3]);
+        anmf.push(0x02); // do not blend with the previous frame
+        anmf.extend_from_slice(&still[12..]); // VP8L chunk following RIFF header
+        write_chunk(&mut body, b"ANMF", &anmf)?;
+    }
+    write_chunk(image, b"RIFF", &body)?;
+    Ok(())
+}
+
+fn encode_gif<W: Write>(frames: Vec<(RgbaImage, u32)>, image: &mut W) -> Result<()> {
+    let mut encoder = GifEncoder::new(image);
+    encoder.set_repeat(Repeat::Infinite)?;
+    encoder.encode_frames(frames.into_iter().map(|(frame, delay)| {
+        Frame::from_parts(frame, 0, 0, Delay::from_numer_denom_ms(delay, 1))
+    }))?;
+    Ok(())
+}
+
+pub(crate) fn encode_animation<W: Write>(
+    frames: Vec<(RgbaImage, u32)>,
+    format: ThumbnailFormat,
+    image: &mut W,
+) -> Result<()> {
+    match format {
+        ThumbnailFormat::Gif => encode_gif(frames, image),
+        ThumbnailFormat::WebP => encode_webp(frames, image),
+        _ => Err(Error::TypeMismatch {
+            val: format.to_string(),
+            expect: "GIF or WEBP".to_string(),
+            found: "format without animation".to_string(),
+        }),
+    }
+}
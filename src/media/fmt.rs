@@ -2,7 +2,7 @@ use std::fmt::{Display, Formatter, Result};
 
 use textwrap::indent;
 
-use crate::media::{AudioDetail, ImageDetail, MediaDetail, TextDetail, TypesDetail, URLDetail, VideoDetail};
+use crate::media::{AudioDetail, ImageDetail, ImageMetadata, MediaDetail, PaletteColor, TextDetail, TypesDetail, URLDetail, VideoDetail};
 
 use super::Media;
 use super::MediaType;
@@ -87,7 +87,73 @@ impl Display for TypesDetail {
 
 impl Display for ImageDetail {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "Image Resolution: {} x {}\nImage Format: {}", self.width, self.height, self.format)
+        write!(f, "Image Resolution: {} x {}\nImage Format: {}", self.width, self.height, self.format)?;
+        if let (Some(frames), Some(duration)) = (self.frames, self.duration) {
+            write!(f, "\nAnimation: {} frames, {:.2}s", frames, duration as f64 / 1000.0)?;
+        }
+        if !self.palette.is_empty() {
+            let palette: Vec<String> = self.palette.iter().map(|c| format!("{}", c)).collect();
+            write!(f, "\nPalette: {}", palette.join(", "))?;
+        }
+        if let Some(v) = &self.metadata {
+            write!(f, "\n{}", v)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PaletteColor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "#{:02X}{:02X}{:02X} ({:.0}%)", self.rgb[0], self.rgb[1], self.rgb[2], self.ratio * 100.0)
+    }
+}
+
+impl Display for ImageMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let mut s: Vec<String> = vec![];
+        match (&self.make, &self.model) {
+            (Some(make), Some(model)) => s.push(format!("Camera: {} {}", make, model)),
+            (Some(v), None) | (None, Some(v)) => s.push(format!("Camera: {}", v)),
+            _ => {}
+        }
+        if let Some(v) = &self.lens {
+            s.push(format!("Lens: {}", v));
+        }
+        if let Some(v) = self.exposure_time {
+            if v > 0.0 && v < 1.0 {
+                s.push(format!("Exposure: 1/{:.0} s", 1.0 / v));
+            } else {
+                s.push(format!("Exposure: {} s", v));
+            }
+        }
+        if let Some(v) = self.f_number {
+            s.push(format!("Aperture: f/{:.1}", v));
+        }
+        if let Some(v) = self.focal_length {
+            s.push(format!("Focal Length: {} mm", v));
+        }
+        if let Some(v) = self.iso {
+            s.push(format!("ISO: {}", v));
+        }
+        if let Some(v) = &self.capture_time {
+            s.push(format!("Capture Time: {}", v));
+        }
+        if let Some(v) = &self.gps {
+            s.push(format!("GPS: {:.6}, {:.6}", v.latitude, v.longitude));
+        }
+        if let Some(v) = self.orientation {
+            s.push(format!("Orientation: {}", v));
+        }
+        if let Some(v) = &self.title {
+            s.push(format!("Title: {}", v));
+        }
+        if let Some(v) = &self.creator {
+            s.push(format!("Creator: {}", v));
+        }
+        if !self.keywords.is_empty() {
+            s.push(format!("Keywords: {}", self.keywords.join(", ")));
+        }
+        write!(f, "{}", s.join("\n"))
     }
 }
 
@@ -114,7 +180,11 @@ impl Display for VideoDetail {
 
 impl Display for URLDetail {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        let _ = f;
-        unimplemented!()
+        write!(f, "URL Schema: {}\nURL Host: {}\nURL Path: {}", self.schema, self.host, self.path)?;
+        if let Some(snapshot) = &self.snapshot {
+            write!(f, "\nSnapshot: {} ({:.2} KB, taken at {})",
+                   snapshot.filename, snapshot.filesize as f64 / 1024.0, snapshot.time_snapshot)?;
+        }
+        Ok(())
     }
 }
\ No newline at end of file
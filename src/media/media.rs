@@ -1,63 +1,112 @@
 use std::collections::HashMap;
 use std::io::BufRead;
 
+use image::codecs::jpeg::JpegEncoder;
 use image::io::Reader as ImageReader;
-use image::ImageFormat;
+use image::{ColorType, DynamicImage, ImageFormat};
 use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
-use serde::__private::TryFrom;
+use std::convert::TryFrom;
 
-use super::super::misc::{Error, Result};
+use super::super::misc::{config, Error, Result};
 use super::*;
+use crate::impl_text_enum;
 
 impl Media {
     pub fn detailize(self, other: Option<HashMap<String, String>>) -> Media {
-        let other = other.unwrap_or(HashMap::new());
+        match self.try_detailize(other) {
+            Ok(Some(detail)) => Media {
+                detail: Some(detail),
+                ..self
+            },
+            Ok(None) => self,
+            Err(e) => {
+                println!("{}", e);
+                self
+            }
+        }
+    }
+
+    // None for media without type.
+    pub(crate) fn try_detailize(
+        &self,
+        other: Option<HashMap<String, String>>,
+    ) -> Result<Option<MediaDetail>> {
+        let mut other = other.unwrap_or(HashMap::new());
         let detail = match &self.kind {
-            MediaType::Image => ImageDetail::get_detail(&self.filepath),
-            MediaType::Text => TextDetail::get_detail(&self.filepath),
-            MediaType::Audio => AudioDetail::get_detail(&self.filepath),
-            MediaType::Video => VideoDetail::get_detail(&self.filepath),
-            MediaType::URL => URLDetail::get_detail(&self.filepath),
-            MediaType::Other => {
-                return {
-                    Media {
-                        detail: Some(MediaDetail {
-                            detail: TypesDetail::Other,
-                            other,
-                        }),
-                        ..self
+            MediaType::Image => ImageDetail::get_detail(&self.filepath)?,
+            MediaType::Text => TextDetail::get_detail(&self.filepath)?,
+            MediaType::Audio => AudioDetail::get_detail(&self.filepath)?,
+            MediaType::Video => VideoDetail::get_detail(&self.filepath)?,
+            MediaType::URL => {
+                // url media keep the url itself as filename, there is no file to look into.
+                if let Ok(query) = URLDetail::get_query(&self.filename) {
+                    for (k, v) in query {
+                        other.entry(k).or_insert(v);
                     }
                 }
+                URLDetail::get_detail(&self.filename)?
             }
-            MediaType::None => return self,
+            MediaType::Other => TypesDetail::Other,
+            MediaType::None => return Ok(None),
         };
-        match &detail {
-            Ok(_) => {}
-            Err(e) => {
-                println!("{}", e);
-            }
-        }
-        if detail.is_err() {
-            return self;
-        }
-        let detail = detail.unwrap();
-        Media {
-            detail: Some(MediaDetail { detail, other }),
-            ..self
-        }
+        Ok(Some(MediaDetail { detail, other }))
     }
 
-    pub fn get_thumbnail<W>(&self, image: &mut W, width: u32, height: u32) -> Result<()>
+    pub fn get_thumbnail<W>(&self, image: &mut W, spec: &ThumbnailSpec) -> Result<()>
     where
         W: std::io::Write,
     {
         match self.kind {
-            MediaType::Image => ImageDetail::get_thumbnail(&self.filepath, image, width, height),
+            MediaType::Image => ImageDetail::get_thumbnail(&self.filepath, image, spec),
+            // filepath of url media should be pointed to the screenshot before thumbnailing.
+            MediaType::URL => match self.get_url_snapshot() {
+                Some(URLSnapshot {
+                    screenshot: Some(_),
+                    ..
+                }) => URLDetail::get_thumbnail(&self.filepath, image, spec),
+                _ => Err(Error::NoThumbnail),
+            },
             _ => Err(Error::NoThumbnail),
         }
     }
 }
 
+impl Media {
+    pub fn get_url_snapshot(&self) -> Option<&URLSnapshot> {
+        match &self.detail {
+            Some(MediaDetail {
+                detail: TypesDetail::URL(detail),
+                ..
+            }) => detail.snapshot.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl Media {
+    pub fn get_image_metadata(&self) -> Option<&ImageMetadata> {
+        match &self.detail {
+            Some(MediaDetail {
+                detail: TypesDetail::Image(detail),
+                ..
+            }) => detail.metadata.as_deref(),
+            _ => None,
+        }
+    }
+
+    // XMP sidecar (.xmp next to the original file) overrides what embedded in image.
+    pub fn apply_xmp_sidecar(&mut self, xmp: &str) {
+        if let Some(MediaDetail {
+            detail: TypesDetail::Image(detail),
+            ..
+        }) = &mut self.detail
+        {
+            let metadata = detail.metadata.get_or_insert_with(Default::default);
+            metadata.merge_xmp(xmp);
+        }
+    }
+}
+
 impl Into<u64> for Media {
     fn into(self) -> u64 {
         self.id
@@ -135,21 +184,45 @@ impl MediaType {
     pub fn is_none(&self) -> bool {
         !self.is_some()
     }
+
+    // Guess by the extension, None for unknown files.
+    pub fn from_path(path: &std::path::Path) -> MediaType {
+        if ImageFormat::from_path(path).is_ok() {
+            return MediaType::Image;
+        }
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "mp4" | "m4v" | "mkv" | "webm" | "mov" | "avi" | "wmv" | "flv" => MediaType::Video,
+            "mp3" | "flac" | "wav" | "ogg" | "opus" | "m4a" | "aac" => MediaType::Audio,
+            "txt" | "md" | "markdown" => MediaType::Text,
+            _ => MediaType::None,
+        }
+    }
 }
 
+// Unsupported types return Err rather than panic, since detailize may run in background
+// for every media.
 trait Detailize {
     fn get_detail(media_path: &str) -> Result<TypesDetail>;
-    fn get_thumbnail<W>(media_path: &str, image: &mut W, width: u32, height: u32) -> Result<()>
+    fn get_thumbnail<W>(media_path: &str, image: &mut W, spec: &ThumbnailSpec) -> Result<()>
     where
         W: std::io::Write;
 }
 
 impl Detailize for ImageDetail {
     fn get_detail(media_path: &str) -> Result<TypesDetail> {
-        let img = ImageReader::open(media_path)?;
-        let format = img.format();
-        let (width, height) = img.into_dimensions()?;
-        let format = match format {
+        // medias are stored without extension, format could only be guessed from content.
+        let img = ImageReader::open(media_path)?.with_guessed_format()?;
+        let image_format = img.format();
+        let (width, height) = match image_format {
+            Some(ImageFormat::WebP) => animation::webp_dimensions(media_path)?,
+            _ => img.into_dimensions()?,
+        };
+        let format = match image_format {
             Some(v) => match v {
                 ImageFormat::Png => "PNG",
                 ImageFormat::Jpeg => "JPG",
@@ -163,14 +236,46 @@ impl Detailize for ImageDetail {
             None => return Err(Error::MediaDecode("Unknown Image format.".to_string())),
         }
         .to_string();
+        let metadata = ImageMetadata::from_file(media_path).unwrap_or(None);
+        // width and height are reported as the image is displayed
+        let (width, height) = match metadata.as_ref().and_then(|m| m.orientation) {
+            Some(5..=8) => (height, width),
+            _ => (width, height),
+        };
+        let animation = match image_format {
+            Some(f @ ImageFormat::Gif) | Some(f @ ImageFormat::WebP) => {
+                animation::read_animation_info(media_path, f)?
+            }
+            _ => None,
+        };
+        // palette and dhash are optional, images which could not be decoded still have their details.
+        let decoded = match image_format {
+            Some(ImageFormat::WebP) => animation::decode_webp(media_path).ok(),
+            _ => ImageReader::open(media_path)?
+                .with_guessed_format()?
+                .decode()
+                .ok(),
+        };
+        let palette = decoded
+            .as_ref()
+            .map(|img| palette::extract_palette(img, config::PALETTE_SIZE))
+            .unwrap_or_default();
+        let dhash = decoded
+            .as_ref()
+            .map(|img| similarity::dhash_to_string(similarity::dhash(img)));
         Ok(TypesDetail::Image(ImageDetail {
             width,
             height,
             format,
+            metadata: metadata.map(Box::new),
+            frames: animation.map(|(frames, _)| frames),
+            duration: animation.map(|(_, duration)| duration),
+            palette,
+            dhash,
         }))
     }
 
-    fn get_thumbnail<W>(media_path: &str, image: &mut W, width: u32, height: u32) -> Result<()>
+    fn get_thumbnail<W>(media_path: &str, image: &mut W, spec: &ThumbnailSpec) -> Result<()>
     where
         W: std::io::Write,
     {
@@ -178,31 +283,49 @@ impl Detailize for ImageDetail {
         let mut buffer_reader = std::io::BufReader::with_capacity(16, f);
         buffer_reader.fill_buf()?;
         let img_format = image::guess_format(buffer_reader.buffer())?;
-        let mut img = ImageReader::new(buffer_reader);
-        img.set_format(img_format);
-        let img = img.decode()?;
+        if spec.animated
+            && matches!(spec.format, ThumbnailFormat::Gif | ThumbnailFormat::WebP)
+            && matches!(img_format, ImageFormat::Gif | ImageFormat::WebP)
+        {
+            let frames = animation::read_preview_frames(media_path, img_format, spec)?;
+            if frames.len() > 1 {
+                return animation::encode_animation(frames, spec.format, image);
+            }
+        }
+        let img = match img_format {
+            ImageFormat::WebP => animation::decode_webp(media_path)?,
+            _ => {
+                let mut img = ImageReader::new(buffer_reader);
+                img.set_format(img_format);
+                img.decode()?
+            }
+        };
+        let orientation = metadata::read_orientation(media_path);
         // TODO: Speed up thumbnailization
-        let thumb = img.thumbnail(width, height);
-        // thumb.write_to(image, ImageFormat::Png)?;
-        thumb.write_to(image, ImageFormat::Jpeg)?;
-        Ok(())
+        let thumb = match orientation {
+            5..=8 => img.thumbnail(spec.height, spec.width), // to be rotated by 90 degrees
+            _ => img.thumbnail(spec.width, spec.height),
+        };
+        let thumb = metadata::apply_orientation(thumb, orientation);
+        spec.encode(&thumb, image)
     }
 }
 
 impl Detailize for TextDetail {
     fn get_detail(media_path: &str) -> Result<TypesDetail> {
         let _ = media_path;
-        unimplemented!()
+        Err(Error::MediaDecode(
+            "Text detail is not supported yet.".to_string(),
+        ))
     }
 
-    fn get_thumbnail<W>(media_path: &str, image: &mut W, width: u32, height: u32) -> Result<()>
+    fn get_thumbnail<W>(media_path: &str, image: &mut W, spec: &ThumbnailSpec) -> Result<()>
     where
         W: std::io::Write,
     {
         let _ = media_path;
         let _ = image;
-        let _ = width;
-        let _ = height;
+        let _ = spec;
         unimplemented!();
     }
 }
@@ -210,17 +333,18 @@ impl Detailize for TextDetail {
 impl Detailize for AudioDetail {
     fn get_detail(media_path: &str) -> Result<TypesDetail> {
         let _ = media_path;
-        unimplemented!()
+        Err(Error::MediaDecode(
+            "Audio detail is not supported yet.".to_string(),
+        ))
     }
 
-    fn get_thumbnail<W>(media_path: &str, image: &mut W, width: u32, height: u32) -> Result<()>
+    fn get_thumbnail<W>(media_path: &str, image: &mut W, spec: &ThumbnailSpec) -> Result<()>
     where
         W: std::io::Write,
     {
         let _ = media_path;
         let _ = image;
-        let _ = width;
-        let _ = height;
+        let _ = spec;
         unimplemented!();
     }
 }
@@ -228,39 +352,154 @@ impl Detailize for AudioDetail {
 impl Detailize for VideoDetail {
     fn get_detail(media_path: &str) -> Result<TypesDetail> {
         let _ = media_path;
-        unimplemented!()
+        Err(Error::MediaDecode(
+            "Video detail is not supported yet.".to_string(),
+        ))
     }
 
-    fn get_thumbnail<W>(media_path: &str, image: &mut W, width: u32, height: u32) -> Result<()>
+    fn get_thumbnail<W>(media_path: &str, image: &mut W, spec: &ThumbnailSpec) -> Result<()>
     where
         W: std::io::Write,
     {
         let _ = media_path;
         let _ = image;
-        let _ = width;
-        let _ = height;
+        let _ = spec;
         unimplemented!();
     }
 }
 
+impl URLDetail {
+    pub fn get_query(url: &str) -> Result<HashMap<String, String>> {
+        let url = url::Url::parse(url)?;
+        Ok(url.query_pairs().into_owned().collect())
+    }
+}
+
 impl Detailize for URLDetail {
     fn get_detail(media_path: &str) -> Result<TypesDetail> {
-        let _ = media_path;
-        unimplemented!()
+        let url = url::Url::parse(media_path)?;
+        Ok(TypesDetail::URL(URLDetail {
+            schema: url.scheme().to_string(),
+            host: url.host_str().unwrap_or("").to_string(),
+            path: url.path().to_string(),
+            snapshot: None,
+        }))
     }
 
-    fn get_thumbnail<W>(media_path: &str, image: &mut W, width: u32, height: u32) -> Result<()>
+    // media_path here is the screenshot attached with snapshot.
+    fn get_thumbnail<W>(media_path: &str, image: &mut W, spec: &ThumbnailSpec) -> Result<()>
     where
         W: std::io::Write,
     {
-        let _ = media_path;
-        let _ = image;
-        let _ = width;
-        let _ = height;
-        unimplemented!();
+        ImageDetail::get_thumbnail(media_path, image, spec)
+    }
+}
+
+impl Default for ThumbnailSpec {
+    fn default() -> Self {
+        ThumbnailSpec::new(
+            config::THUMBNAIL_SIZE.0,
+            config::THUMBNAIL_SIZE.1,
+            ThumbnailFormat::Jpeg,
+        )
+    }
+}
+
+impl ThumbnailSpec {
+    pub fn new(width: u32, height: u32, format: ThumbnailFormat) -> Self {
+        ThumbnailSpec {
+            width,
+            height,
+            format,
+            quality: config::THUMBNAIL_QUALITY,
+            animated: false,
+        }
+    }
+
+    // Small square icons for grid view.
+    pub fn icon() -> Self {
+        ThumbnailSpec::new(
+            config::THUMBNAIL_ICON_SIZE.0,
+            config::THUMBNAIL_ICON_SIZE.1,
+            ThumbnailFormat::Jpeg,
+        )
+    }
+
+    // Medium images for previewing.
+    pub fn preview() -> Self {
+        ThumbnailSpec::new(
+            config::THUMBNAIL_PREVIEW_SIZE.0,
+            config::THUMBNAIL_PREVIEW_SIZE.1,
+            ThumbnailFormat::Jpeg,
+        )
+    }
+
+    pub fn with_format(self, format: ThumbnailFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    // Animated preview for GIF and WEBP thumbnails of animated images.
+    pub fn with_animation(self, animated: bool) -> Self {
+        Self { animated, ..self }
+    }
+
+    // Jpeg only, see ThumbnailSpec.
+    pub fn with_quality(self, quality: u8) -> Self {
+        Self {
+            quality: quality.clamp(1, 100),
+            ..self
+        }
+    }
+
+    // For HiDPI screens, e.g. scaled(2) for retina.
+    pub fn scaled(self, factor: u32) -> Self {
+        Self {
+            width: self.width * factor,
+            height: self.height * factor,
+            ..self
+        }
+    }
+
+    pub(crate) fn encode<W>(&self, thumb: &DynamicImage, image: &mut W) -> Result<()>
+    where
+        W: std::io::Write,
+    {
+        match self.format {
+            ThumbnailFormat::Jpeg => {
+                let thumb = thumb.to_rgb8();
+                JpegEncoder::new_with_quality(image, self.quality).encode(
+                    &thumb,
+                    thumb.width(),
+                    thumb.height(),
+                    ColorType::Rgb8,
+                )?;
+            }
+            ThumbnailFormat::Png => thumb.write_to(image, ImageFormat::Png)?,
+            ThumbnailFormat::Gif => thumb.write_to(image, ImageFormat::Gif)?,
+            ThumbnailFormat::WebP => {
+                // only lossless webp could be encoded, quality makes no sense here.
+                let thumb = thumb.to_rgba8();
+                image_webp::WebPEncoder::new(image)
+                    .encode(
+                        &thumb,
+                        thumb.width(),
+                        thumb.height(),
+                        image_webp::ColorType::Rgba8,
+                    )
+                    .map_err(|e| Error::MediaDecode(format!("Encode webp failed: {}", e)))?;
+            }
+        }
+        Ok(())
     }
 }
 
+impl_text_enum!(ThumbnailFormat, "thumbnail format", {
+    Jpeg => "JPEG" | "JPG",
+    Png => "PNG",
+    WebP => "WEBP",
+    Gif => "GIF",
+});
+
 impl std::str::FromStr for MediaType {
     type Err = Error;
 
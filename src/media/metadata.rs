@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+use chrono::NaiveDateTime;
+use exif::{In, Tag, Value};
+use image::DynamicImage;
+use regex::Regex;
+
+use super::super::misc::Result;
+use super::{GpsCoordinate, ImageMetadata};
+
+const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+
+impl ImageMetadata {
+    // EXIF goes first, then IPTC and embedded XMP which are usually edited later.
+    pub(crate) fn from_file(media_path: &str) -> Result<Option<ImageMetadata>> {
+        let mut metadata = ImageMetadata::default();
+        let mut file = BufReader::new(File::open(media_path)?);
+        if let Ok(exif) = exif::Reader::new().read_from_container(&mut file) {
+            metadata.merge_exif(&exif);
+        }
+        let (xmp, iptc) = read_jpeg_segments(media_path)?;
+        if let Some(iptc) = iptc {
+            metadata.merge_iptc(&iptc);
+        }
+        if let Some(xmp) = xmp {
+            metadata.merge_xmp(&xmp);
+        }
+        Ok(if metadata.is_empty() {
+            None
+        } else {
+            Some(metadata)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &ImageMetadata::default()
+    }
+
+    fn merge_exif(&mut self, exif: &exif::Exif) {
+        let field = |tag: Tag| exif.get_field(tag, In::PRIMARY).map(|f| &f.value);
+        let ascii = |tag: Tag| match field(tag) {
+            Some(Value::Ascii(v)) => v
+                .first()
+                .map(|s| String::from_utf8_lossy(s).trim().to_string())
+                .filter(|s| !s.is_empty()),
+            _ => None,
+        };
+        let rational = |tag: Tag| match field(tag) {
+            Some(Value::Rational(v)) => v.first().map(|r| r.to_f64()).filter(|v| v.is_finite()),
+            _ => None,
+        };
+
+        self.make = ascii(Tag::Make).or(self.make.take());
+        self.model = ascii(Tag::Model).or(self.model.take());
+        self.lens = ascii(Tag::LensModel).or(self.lens.take());
+        self.exposure_time = rational(Tag::ExposureTime).or(self.exposure_time);
+        self.f_number = rational(Tag::FNumber).or(self.f_number);
+        self.focal_length = rational(Tag::FocalLength).or(self.focal_length);
+        self.iso = field(Tag::PhotographicSensitivity)
+            .and_then(|v| v.get_uint(0))
+            .or(self.iso);
+        self.orientation = field(Tag::Orientation)
+            .and_then(|v| v.get_uint(0))
+            .map(|v| v as u16)
+            .or(self.orientation);
+        self.capture_time = [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+            .iter()
+            .filter_map(|tag| match field(*tag) {
+                Some(Value::Ascii(v)) => v.first().and_then(|s| parse_exif_time(s)),
+                _ => None,
+            })
+            .next()
+            .or(self.capture_time);
+
+        let dms = |tag: Tag, ref_tag: Tag, negative: &str| -> Option<f64> {
+            let v = match field(tag) {
+                Some(Value::Rational(v)) if v.len() >= 3 => {
+                    v[0].to_f64() + v[1].to_f64() / 60.0 + v[2].to_f64() / 3600.0
+                }
+                _ => return None,
+            };
+            match ascii(ref_tag) {
+                Some(r) if r.eq_ignore_ascii_case(negative) => Some(-v),
+                _ => Some(v),
+            }
+        };
+        if let (Some(latitude), Some(longitude)) = (
+            dms(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+            dms(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+        ) {
+            let below_sea =
+                matches!(field(Tag::GPSAltitudeRef), Some(Value::Byte(v)) if v.first() == Some(&1));
+            self.gps = Some(GpsCoordinate {
+                latitude,
+                longitude,
+                altitude: rational(Tag::GPSAltitude).map(|v| if below_sea { -v } else { v }),
+            });
+        }
+    }
+
+    // IPTC-IIM records, only the application record (2) is concerned.
+    fn merge_iptc(&mut self, iim: &[u8]) {
+        let mut i = 0;
+        while i + 5 <= iim.len() && iim[i] == 0x1C {
+            let (record, dataset) = (iim[i + 1], iim[i + 2]);
+            let len = u16::from_be_bytes([iim[i + 3], iim[i + 4]]) as usize;
+            if len & 0x8000 != 0 || i + 5 + len > iim.len() {
+                break; // extended dataset is not used by the fields we want
+            }
+            let value = String::from_utf8_lossy(&iim[i + 5..i + 5 + len])
+                .trim()
+                .to_string();
+            i += 5 + len;
+            if record != 2 || value.is_empty() {
+                continue;
+            }
+            match dataset {
+                5 => self.title = Some(value),
+                25 if !self.keywords.contains(&value) => self.keywords.push(value),
+                80 => self.creator = Some(value),
+                90 => self.city = Some(value),
+                101 => self.country = Some(value),
+                105 => self.headline = Some(value),
+                116 => self.copyright = Some(value),
+                120 => self.description = Some(value),
+                _ => {}
+            }
+        }
+    }
+
+    // XMP packet, either embedded or from a sidecar file. Values found here take precedence.
+    pub fn merge_xmp(&mut self, xmp: &str) {
+        let xmp = Xmp::parse(xmp);
+        let text = |names: &[&str]| names.iter().find_map(|name| xmp.value(name));
+        let number = |names: &[&str]| text(names).and_then(|v| parse_xmp_number(&v));
+
+        self.make = text(&["tiff:Make"]).or(self.make.take());
+        self.model = text(&["tiff:Model"]).or(self.model.take());
+        self.lens = text(&["exifEX:LensModel", "aux:Lens"]).or(self.lens.take());
+        self.exposure_time = number(&["exif:ExposureTime"]).or(self.exposure_time);
+        self.f_number = number(&["exif:FNumber"]).or(self.f_number);
+        self.focal_length = number(&["exif:FocalLength"]).or(self.focal_length);
+        self.iso = number(&["exifEX:PhotographicSensitivity", "exif:ISOSpeedRatings"])
+            .map(|v| v as u32)
+            .or(self.iso);
+        self.orientation = number(&["tiff:Orientation"])
+            .map(|v| v as u16)
+            .or(self.orientation);
+        self.capture_time = text(&[
+            "exif:DateTimeOriginal",
+            "xmp:CreateDate",
+            "photoshop:DateCreated",
+        ])
+        .and_then(|v| parse_xmp_time(&v))
+        .or(self.capture_time);
+        if let (Some(latitude), Some(longitude)) = (
+            text(&["exif:GPSLatitude"]).and_then(|v| parse_xmp_gps(&v)),
+            text(&["exif:GPSLongitude"]).and_then(|v| parse_xmp_gps(&v)),
+        ) {
+            let altitude = number(&["exif:GPSAltitude"]).map(|v| {
+                if text(&["exif:GPSAltitudeRef"]).as_deref() == Some("1") {
+                    -v
+                } else {
+                    v
+                }
+            });
+            self.gps = Some(GpsCoordinate {
+                latitude,
+                longitude,
+                altitude,
+            });
+        }
+
+        self.title = text(&["dc:title"]).or(self.title.take());
+        self.description = text(&["dc:description"]).or(self.description.take());
+        self.creator = text(&["dc:creator"]).or(self.creator.take());
+        self.copyright = text(&["dc:rights"]).or(self.copyright.take());
+        self.headline = text(&["photoshop:Headline"]).or(self.headline.take());
+        self.city = text(&["photoshop:City"]).or(self.city.take());
+        self.country = text(&["photoshop:Country"]).or(self.country.take());
+        for keyword in xmp.list("dc:subject") {
+            if !self.keywords.contains(&keyword) {
+                self.keywords.push(keyword);
+            }
+        }
+    }
+}
+
+// EXIF orientation of image, 1 (normal) if absent.
+pub(crate) fn read_orientation(media_path: &str) -> u16 {
+    let orientation = File::open(media_path).ok().and_then(|f| {
+        let exif = exif::Reader::new()
+            .read_from_container(&mut BufReader::new(f))
+            .ok()?;
+        exif.get_field(Tag::Orientation, In::PRIMARY)?
+            .value
+            .get_uint(0)
+    });
+    match orientation {
+        Some(v @ 1..=8) => v as u16,
+        _ => 1,
+    }
+}
+
+// Transform decoded pixels to how the image should be displayed.
+pub(crate) fn apply_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// Walk through JPEG markers till the image data, picking APP1 XMP and APP13 IPTC out.
+fn read_jpeg_segments(media_path: &str) -> Result<(Option<String>, Option<Vec<u8>>)> {
+    let mut file = BufReader::new(File::open(media_path)?);
+    let mut marker = [0u8; 2];
+    if file.read_exact(&mut marker).is_err() || marker != [0xFF, 0xD8] {
+        return Ok((None, None));
+    }
+    let (mut xmp, mut iptc) = (None, None);
+    loop {
+        if file.read_exact(&mut marker).is_err() || marker[0] != 0xFF {
+            break;
+        }
+        match marker[1] {
+            0xD0..=0xD8 | 0x01 => continue, // no length
+            0xD9 | 0xDA => break,           // EOI or SOS
+            _ => {}
+        }
+        // truncated file keeps what is read so far
+        let mut len = [0u8; 2];
+        if file.read_exact(&mut len).is_err() {
+            break;
+        }
+        let len = (u16::from_be_bytes(len) as usize).saturating_sub(2);
+        let mut data = vec![0u8; len];
+        if file.read_exact(&mut data).is_err() {
+            break;
+        }
+        if marker[1] == 0xE1 && data.starts_with(XMP_SIGNATURE) {
+            xmp = Some(String::from_utf8_lossy(&data[XMP_SIGNATURE.len()..]).to_string());
+        } else if marker[1] == 0xED && data.starts_with(PHOTOSHOP_SIGNATURE) {
+            iptc = photoshop_iptc(&data[PHOTOSHOP_SIGNATURE.len()..]).or(iptc);
+        }
+    }
+    Ok((xmp, iptc))
+}
+
+// Photoshop image resource blocks, IPTC-IIM is stored in resource 0x0404.
+fn photoshop_iptc(mut data: &[u8]) -> Option<Vec<u8>> {
+    while data.len() >= 12 && data.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([data[4], data[5]]);
+        let name_len = data[6] as usize;
+        let name_size = (name_len + 1 + 1) & !1; // padded to even
+        let offset = 6 + name_size;
+        if offset + 4 > data.len() {
+            return None;
+        }
+        let size = u32::from_be_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ]) as usize;
+        let begin = offset + 4;
+        if begin + size > data.len() {
+            return None;
+        }
+        if id == 0x0404 {
+            return Some(data[begin..begin + size].to_vec());
+        }
+        data = &data[(begin + size + (size & 1)).min(data.len())..];
+    }
+    None
+}
+
+// Properties of an XMP packet by qualified name, both attribute and element forms,
+// collected in one pass. The first occurrence of a name wins.
+struct Xmp<'a> {
+    attributes: HashMap<&'a str, &'a str>,
+    elements: HashMap<&'a str, &'a str>,
+}
+
+impl<'a> Xmp<'a> {
+    fn parse(xmp: &'a str) -> Self {
+        lazy_static::lazy_static! {
+            static ref ATTRIBUTE: Regex = Regex::new(r#"([\w.-]+:[\w.-]+)\s*=\s*"([^"]*)""#).unwrap();
+            static ref OPEN_TAG: Regex = Regex::new(r"<([\w.-]+:[\w.-]+)(?:\s[^>]*)?>").unwrap();
+        }
+        let mut attributes = HashMap::new();
+        for c in ATTRIBUTE.captures_iter(xmp) {
+            let (name, value) = (c.get(1).unwrap(), c.get(2).unwrap());
+            attributes.entry(name.as_str()).or_insert(value.as_str());
+        }
+        let mut elements = HashMap::new();
+        for c in OPEN_TAG.captures_iter(xmp) {
+            let name = c.get(1).unwrap().as_str();
+            if elements.contains_key(name) {
+                continue;
+            }
+            let rest = &xmp[c.get(0).unwrap().end()..];
+            if let Some(end) = rest.find(&format!("</{}>", name)) {
+                elements.insert(name, &rest[..end]);
+            }
+        }
+        Xmp {
+            attributes,
+            elements,
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<String> {
+        if let Some(value) = self.attributes.get(name) {
+            return Some(unescape_xml(value)).filter(|v| !v.is_empty());
+        }
+        let inner = self.elements.get(name)?;
+        if inner.contains("rdf:li") {
+            xmp_li(inner).into_iter().next()
+        } else {
+            Some(unescape_xml(inner.trim())).filter(|v| !v.is_empty())
+        }
+    }
+
+    fn list(&self, name: &str) -> Vec<String> {
+        match self.elements.get(name) {
+            Some(inner) => xmp_li(inner),
+            None => vec![],
+        }
+    }
+}
+
+fn xmp_li(inner: &str) -> Vec<String> {
+    lazy_static::lazy_static! {
+        static ref LI: Regex = Regex::new(r"(?s)<rdf:li(?:\s[^>]*)?>(.*?)</rdf:li>").unwrap();
+    }
+    LI.captures_iter(inner)
+        .map(|c| unescape_xml(c[1].trim()))
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn parse_exif_time(s: &[u8]) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(std::str::from_utf8(s).ok()?.trim(), "%Y:%m:%d %H:%M:%S").ok()
+}
+
+// XMP dates are ISO 8601 and may omit seconds or carry time zone, local time is kept.
+fn parse_xmp_time(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim();
+    let local = match s.find(['+', 'Z']).or_else(|| {
+        // '-' of timezone only appears after the time part
+        s.rfind('-').filter(|i| *i > 10)
+    }) {
+        Some(i) => &s[..i],
+        None => s,
+    };
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|fmt| NaiveDateTime::parse_from_str(local, fmt).ok())
+}
+
+// Rationals in XMP are written as "num/denom".
+fn parse_xmp_number(s: &str) -> Option<f64> {
+    let v = match s.split_once('/') {
+        Some((n, d)) => n.trim().parse::<f64>().ok()? / d.trim().parse::<f64>().ok()?,
+        None => s.trim().parse().ok()?,
+    };
+    Some(v).filter(|v| v.is_finite())
+}
+
+// GPS coordinate in XMP looks like "35,40.5N" or "35,40,30N".
+fn parse_xmp_gps(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let direction = s.chars().last()?;
+    let parts = s[..s.len() - direction.len_utf8()]
+        .split(',')
+        .map(|v| v.trim().parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    let v = parts
+        .iter()
+        .zip([1.0, 60.0, 3600.0].iter())
+        .map(|(v, d)| v / d)
+        .sum::<f64>();
+    match direction {
+        'N' | 'E' => Some(v),
+        'S' | 'W' => Some(-v),
+        _ => None,
+    }
+}
@@ -1,5 +1,9 @@
+mod animation;
 mod fmt;
 mod media;
+mod metadata;
+mod palette;
+pub(crate) mod similarity;
 
 pub enum MediaUpdateKey {
     Filename,
@@ -53,8 +57,8 @@ pub struct Media {
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct MediaDetail {
-    detail: TypesDetail,
-    other: std::collections::HashMap<String, String>,
+    pub(crate) detail: TypesDetail,
+    pub(crate) other: std::collections::HashMap<String, String>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -72,6 +76,74 @@ pub struct ImageDetail {
     height: u32,
     width: u32,
     format: String,
+    #[serde(default)]
+    pub(crate) metadata: Option<Box<ImageMetadata>>,
+    // for animated images only, duration is in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) frames: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) duration: Option<u64>,
+    // dominant colors, the most one goes first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) palette: Vec<PaletteColor>,
+    // perceptual hash in hex, for finding similar images.
+    // Kept as null if not decodable, a missing one means detailized before dhash existed.
+    #[serde(default)]
+    pub(crate) dhash: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PaletteColor {
+    pub rgb: [u8; 3],
+    pub ratio: f32, // of pixels in this color
+}
+
+// Shooting information from EXIF, IPTC and XMP (embedded or sidecar).
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ImageMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub make: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lens: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure_time: Option<f64>, // In second
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub f_number: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_length: Option<f64>, // In millimeter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iso: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capture_time: Option<chrono::NaiveDateTime>, // Local time of camera
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gps: Option<GpsCoordinate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<u16>, // EXIF orientation, 1 ~ 8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headline: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub creator: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copyright: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct GpsCoordinate {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>, // In meter
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -104,6 +176,41 @@ pub struct URLDetail {
     schema: String,
     host: String,
     path: String,
+    #[serde(default)]
+    pub(crate) snapshot: Option<URLSnapshot>,
+}
+
+// Offline copy of the page behind an url media, produced by other tools.
+// Files are stored in medias folder by their hash just like normal medias.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct URLSnapshot {
+    pub hash: String,
+    pub filename: String,
+    pub filesize: usize,
+    pub time_snapshot: chrono::DateTime<chrono::Local>,
+    pub screenshot: Option<String>, // hash of screenshot image
+    pub screenshot_size: usize,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThumbnailFormat {
+    Jpeg,
+    Png,
+    WebP,
+    Gif,
+}
+
+// Thumbnails are cached by hash of media, width, height, format and animated.
+// Quality only applies to Jpeg, Png, Gif and WebP are always encoded losslessly
+// and the quality is just kept with the cached one.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ThumbnailSpec {
+    pub width: u32,
+    pub height: u32,
+    pub format: ThumbnailFormat,
+    pub quality: u8, // 1 ~ 100, ignored by lossless formats including WebP
+    #[serde(default)]
+    pub animated: bool, // preview of animated image in GIF or WEBP, still one for others
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,88 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+use super::PaletteColor;
+
+// Pixels are sampled from a small copy, it is enough for dominant colors.
+const SAMPLE_SIZE: u32 = 64;
+// Colors closer than this are merged, they look the same.
+const MERGE_DISTANCE: i32 = 24;
+
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (min, max) = pixels.iter().fold((u8::MAX, u8::MIN), |(min, max), p| {
+                (min.min(p[c]), max.max(p[c]))
+            });
+            (c, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap()
+}
+
+// Median cut: split the bucket with the widest channel range at its median until enough buckets.
+pub(crate) fn extract_palette(image: &DynamicImage, size: usize) -> Vec<PaletteColor> {
+    // nearest sampling keeps the original colors without blending
+    let pixels: Vec<[u8; 3]> = image
+        .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Nearest)
+        .to_rgb8()
+        .pixels()
+        .map(|p| p.0)
+        .collect();
+    let total = pixels.len();
+    if total == 0 {
+        return vec![];
+    }
+    let mut buckets = vec![pixels];
+    while buckets.len() < size {
+        let (index, (channel, range)) = buckets
+            .iter()
+            .map(|bucket| widest_channel(bucket))
+            .enumerate()
+            .max_by_key(|(_, (_, range))| *range)
+            .unwrap();
+        if range == 0 {
+            break; // every bucket is in one color
+        }
+        let mut bucket = buckets.swap_remove(index);
+        bucket.sort_unstable_by_key(|p| p[channel]);
+        let upper = bucket.split_off(bucket.len() / 2);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+    // (sum of each channel, count of pixels)
+    let mut merged: Vec<([usize; 3], usize)> = vec![];
+    for bucket in buckets.iter() {
+        let mut sum = [0usize; 3];
+        for p in bucket {
+            (0..3).for_each(|c| sum[c] += p[c] as usize);
+        }
+        let mean = mean_color(&sum, bucket.len());
+        match merged.iter_mut().find(|(s, n)| {
+            squared_distance(&mean_color(s, *n), &mean) < MERGE_DISTANCE * MERGE_DISTANCE
+        }) {
+            Some((s, n)) => {
+                (0..3).for_each(|c| s[c] += sum[c]);
+                *n += bucket.len();
+            }
+            None => merged.push((sum, bucket.len())),
+        }
+    }
+    let mut palette: Vec<PaletteColor> = merged
+        .iter()
+        .map(|(sum, n)| PaletteColor {
+            rgb: mean_color(sum, *n),
+            ratio: *n as f32 / total as f32,
+        })
+        .collect();
+    palette.sort_by(|a, b| b.ratio.total_cmp(&a.ratio));
+    palette
+}
+
+fn mean_color(sum: &[usize; 3], count: usize) -> [u8; 3] {
+    [0, 1, 2].map(|c| (sum[c] / count) as u8)
+}
+
+fn squared_distance(a: &[u8; 3], b: &[u8; 3]) -> i32 {
+    (0..3).map(|c| (a[c] as i32 - b[c] as i32).pow(2)).sum()
+}
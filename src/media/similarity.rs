@@ -0,0 +1,28 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+// Difference hash: each bit tells whether a pixel is brighter than its right neighbour in a 9x8 grayscale copy.
+// Resized or recompressed copies of an image differ in only a few bits.
+pub(crate) fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+    hash
+}
+
+// Stored as hex in json, since json numbers in sqlite could not hold all u64.
+pub(crate) fn dhash_to_string(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+// Count of different bits, None if any hash is broken.
+pub(crate) fn dhash_distance(a: &str, b: &str) -> Option<u32> {
+    let a = u64::from_str_radix(a, 16).ok()?;
+    let b = u64::from_str_radix(b, 16).ok()?;
+    Some((a ^ b).count_ones())
+}
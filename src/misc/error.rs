@@ -28,6 +28,8 @@ impl std::fmt::Display for Error {
             NoneError => write!(f, "Some values goes none."), // TODO: indicated error msg
             MediaDecode(s) => write!(f, "Media decode error: {}", s),
             NoThumbnail => write!(f, "Media no Thumbnail"),
+            Cancelled => write!(f, "Request is cancelled."),
+            Cycle(s) => write!(f, "Cycle is not allowed: {}.", s),
             InternalSync(e) => write!(f, "Internal Sync Error. ({})", e)
         }
     }
@@ -73,4 +75,9 @@ impl From<std::sync::mpsc::RecvError> for Error {
     fn from(err: std::sync::mpsc::RecvError) -> Self {
         Error::InternalSync(Box::new(err))
     }
-}
\ No newline at end of file
+}
+impl From<url::ParseError> for Error {
+    fn from(err: url::ParseError) -> Self {
+        Error::Other(format!("URL error: {}", err))
+    }
+}
@@ -8,6 +8,7 @@ pub mod config {
     pub const THUMBNAIL_DATABASE_FN: &str = "thumbnail.db";
     pub const FINGERPRINT_FN: &str = ".shiromana";
     pub const DEFAULT_MEDIAS_FOLDER: &str = "medias";
+    pub const SNAPSHOTS_FOLDER: &str = "snapshots"; // in medias folder, apart from media files
     pub const MEDIAS_HASH_LEVEL: u32 = 1;
     // max files is only for warning
     pub const MEDIAS_FOLDER_MAX_FILES: u32 = 10000;
@@ -15,6 +16,16 @@ pub mod config {
     pub const DEFAULT_HASH_ALGO: &str = "BLAKE3";
     pub const LOCKFILE: &str = ".LOCK";
     pub const THUMBNAIL_SIZE: (u32, u32) = (200, 300); // WIDGHT HEIGHT
+    pub const THUMBNAIL_ICON_SIZE: (u32, u32) = (96, 96);
+    pub const THUMBNAIL_PREVIEW_SIZE: (u32, u32) = (1024, 1024);
+    pub const THUMBNAIL_QUALITY: u8 = 85;
+    pub const THUMBNAIL_BUDGET: usize = 256 * 1024 * 1024; // 256 MiB
+    pub const ANIMATED_THUMBNAIL_FRAMES: usize = 48;
+    pub const ANIMATED_THUMBNAIL_FPS: u32 = 10;
+    pub const PALETTE_SIZE: usize = 5;
+    pub const SIMILAR_DHASH_DISTANCE: u32 = 10; // of 64 bits
+    pub const SAME_FOLDER_WEIGHT: f64 = 0.5;
+    pub const FUZZY_TAG_CANDIDATES: usize = 1000; // most used captions and aliases
 }
 
 #[derive(Debug)]
@@ -44,6 +55,8 @@ pub enum Error {
     MediaDecode(String),
     NoneError,
     NoThumbnail,
+    Cancelled,
+    Cycle(String),
     InternalSync(Box<dyn std::error::Error + Sync + Send>),
 }
 
@@ -6,4 +6,99 @@ pub fn is_valid_filename(s: &str) -> bool {
         static ref RE: Regex = Regex::new(r#"^[^\\/:\*\?"'<>|]{1,120}$"#).unwrap();
     }
     RE.find_iter(s).count() != 0
-}
\ No newline at end of file
+}
+
+// Query keys which only exist for tracking visitors and never change the target resource.
+const URL_TRACKING_PARAMS: [&str; 12] = [
+    "fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_ga", "_gl",
+    "ref_src", "spm",
+];
+
+// Normalize url before hashing so the same resource is always stored once.
+// Scheme and host are lowercased and default ports are dropped by the parser itself.
+// Query pairs are sorted by key as they are written, the path is kept since "/dir/" and "/dir" may differ.
+pub fn normalize_url(url: &str) -> super::Result<url::Url> {
+    let mut url = url::Url::parse(url.trim())?;
+    let mut query: Vec<(String, &str)> = url
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter_map(|pair| {
+            let key = url::form_urlencoded::parse(pair.as_bytes())
+                .next()?
+                .0
+                .to_lowercase();
+            (!key.starts_with("utm_") && !URL_TRACKING_PARAMS.contains(&key.as_str()))
+                .then_some((key, pair))
+        })
+        .collect();
+    query.sort_by(|a, b| a.0.cmp(&b.0));
+    let query: Vec<String> = query
+        .into_iter()
+        .map(|(_, pair)| pair.to_string())
+        .collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.set_query(Some(&query.join("&")));
+    }
+    if url.fragment() == Some("") {
+        url.set_fragment(None);
+    }
+    Ok(url)
+}
+
+// Fold caption for matching: NFKC unifies full/half width forms, then case and latin diacritics
+// are dropped. Only U+0300..U+036F are stripped, so Japanese dakuten like "が" stay different from "か".
+pub fn normalize_caption(caption: &str) -> String {
+    use unicode_normalization::UnicodeNormalization;
+    let folded: String = caption.nfkc().collect::<String>().to_lowercase();
+    let stripped: String = folded
+        .nfd()
+        .filter(|c| !('\u{0300}'..='\u{036F}').contains(c))
+        .nfc()
+        .collect();
+    stripped.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+pub(crate) fn has_column(
+    db: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+) -> super::Result<bool> {
+    Ok(db.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?) WHERE name = ?);",
+        rusqlite::params![table, column],
+        |row| row.get(0),
+    )?)
+}
+
+// Natural order for filenames, "2.jpg" goes before "10.jpg". Digits are compared by their value,
+// and other parts are compared case-insensitively.
+pub fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    fn chunks(s: &str) -> Vec<(bool, String)> {
+        let mut chunks: Vec<(bool, String)> = vec![];
+        for c in s.chars() {
+            let is_digit = c.is_ascii_digit();
+            match chunks.last_mut() {
+                Some((digit, chunk)) if *digit == is_digit => chunk.push(c),
+                _ => chunks.push((is_digit, c.to_string())),
+            }
+        }
+        chunks
+    }
+    let (a_chunks, b_chunks) = (chunks(a), chunks(b));
+    for ((a_digit, a), (b_digit, b)) in a_chunks.iter().zip(b_chunks.iter()) {
+        let ordering = if *a_digit && *b_digit {
+            let (a, b) = (a.trim_start_matches('0'), b.trim_start_matches('0'));
+            a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+        } else {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        };
+        if ordering != std::cmp::Ordering::Equal {
+            return ordering;
+        }
+    }
+    a_chunks.len().cmp(&b_chunks.len()).then_with(|| a.cmp(b))
+}